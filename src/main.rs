@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use std::borrow::Cow;
+
 mod token_matcher;
 
 ///
@@ -10,11 +12,65 @@ mod token_matcher;
 pub enum PatternASTNode {
     Literal(String),
     Wildcard,
+    /// An integer in the inclusive range `min..=max`, written in canonical decimal form
+    NumRange {
+        min: i64,
+        max: i64,
+    },
+    /// The inner node repeated `min` to `max` times, or at least `min` times if `max` is `None`
+    Repeat {
+        node: Box<PatternASTNode>,
+        min: usize,
+        max: Option<usize>,
+    },
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct PatternAST(pub Vec<PatternASTNode>);
 
+impl PatternAST {
+    ///
+    /// Normalize the pattern without changing what it matches:
+    /// empty literals are removed, adjacent literals merged and runs of wildcards collapsed.
+    ///
+    /// A pattern of only wildcards and empty literals thereby becomes a single `Wildcard`,
+    /// the match-all pattern.
+    ///
+    pub fn normalize(&self) -> PatternAST {
+        let mut nodes: Vec<PatternASTNode> = vec![];
+
+        for node in &self.0 {
+            match (nodes.last_mut(), node) {
+                (_, PatternASTNode::Literal(text)) if text.is_empty() => {}
+                (Some(PatternASTNode::Wildcard), PatternASTNode::Wildcard) => {}
+                (Some(PatternASTNode::Literal(prev_text)), PatternASTNode::Literal(text)) => {
+                    prev_text.push_str(text);
+                }
+                _ => nodes.push(node.clone()),
+            }
+        }
+
+        if nodes.is_empty() && !self.0.is_empty() {
+            // Only empty literals, which match the empty token:
+            nodes.push(PatternASTNode::Literal(String::new()));
+        }
+
+        PatternAST(nodes)
+    }
+
+    ///
+    /// Whether the pattern matches everything, i.e. normalizes to a single `Wildcard`.
+    ///
+    pub fn is_match_all(&self) -> bool {
+        self.0.contains(&PatternASTNode::Wildcard)
+            && self.0.iter().all(|node| match node {
+                PatternASTNode::Wildcard => true,
+                PatternASTNode::Literal(text) => text.is_empty(),
+                PatternASTNode::NumRange { .. } | PatternASTNode::Repeat { .. } => false,
+            })
+    }
+}
+
 /// A search "Term" - based on https://docs.rs/tantivy/0.14.0/tantivy/struct.Term.html
 #[derive(Default)]
 pub struct Term(pub Vec<u8>);
@@ -24,18 +80,53 @@ impl Term {
         std::str::from_utf8(&self.0).unwrap()
     }
 
+    ///
+    /// Replace the text, reusing the existing buffer.
+    ///
     pub fn set_text(&mut self, text: &str) {
-        self.0 = text.as_bytes().iter().cloned().collect();
+        self.0.clear();
+        self.0.extend_from_slice(text.as_bytes());
+    }
+
+    pub fn as_term_ref(&self) -> TermRef<'_> {
+        TermRef(&self.0)
+    }
+}
+
+///
+/// A search term borrowing its bytes, the no-copy counterpart of `Term`.
+///
+/// Matchers pass the matched token itself as a `TermRef` when looking up doc freqs.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TermRef<'a>(pub &'a [u8]);
+
+impl<'a> TermRef<'a> {
+    pub fn text(&self) -> &'a str {
+        std::str::from_utf8(self.0).unwrap()
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+}
+
+impl<'a> From<&'a str> for TermRef<'a> {
+    fn from(text: &'a str) -> Self {
+        TermRef(text.as_bytes())
     }
 }
 
 ///
 /// Utility for timing things
 ///
+/// With the `serde` feature, it serializes to its `milestones` and `durations` in microseconds.
+/// Deserializing only reads the milestones, the durations being derived from them.
+///
 #[derive(Clone, Debug)]
 pub struct PerfTimer {
     pub start_instant: std::time::Instant,
-    pub milestones: Vec<(&'static str, std::time::Duration)>,
+    pub milestones: Vec<(Cow<'static, str>, std::time::Duration)>,
 }
 
 impl PerfTimer {
@@ -46,17 +137,18 @@ impl PerfTimer {
         }
     }
 
-    pub fn add_milestone(&mut self, name: &'static str) {
-        self.milestones.push((name, self.start_instant.elapsed()));
+    pub fn add_milestone(&mut self, name: impl Into<Cow<'static, str>>) {
+        self.milestones
+            .push((name.into(), self.start_instant.elapsed()));
     }
 
-    pub fn durations(&self) -> Vec<(&'static str, std::time::Duration)> {
+    pub fn durations(&self) -> Vec<(Cow<'static, str>, std::time::Duration)> {
         let mut prev_duration = std::time::Duration::from_secs(0);
         let mut durations = vec![];
 
         for (name, duration) in self.milestones.iter() {
             let current_duration = duration.checked_sub(prev_duration).unwrap();
-            durations.push((*name, current_duration));
+            durations.push((name.clone(), current_duration));
             prev_duration = *duration;
         }
 
@@ -64,6 +156,77 @@ impl PerfTimer {
     }
 }
 
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PerfTimerMicros {
+    milestones: Vec<(Cow<'static, str>, u64)>,
+    durations: Vec<(Cow<'static, str>, u64)>,
+}
+
+#[cfg(feature = "serde")]
+fn durations_as_micros(
+    durations: &[(Cow<'static, str>, std::time::Duration)],
+) -> Vec<(Cow<'static, str>, u64)> {
+    durations
+        .iter()
+        .map(|(name, duration)| (name.clone(), duration.as_micros() as u64))
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PerfTimer {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PerfTimerMicros {
+            milestones: durations_as_micros(&self.milestones),
+            durations: durations_as_micros(&self.durations()),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PerfTimer {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let micros = PerfTimerMicros::deserialize(deserializer)?;
+
+        Ok(Self {
+            start_instant: std::time::Instant::now(),
+            milestones: micros
+                .milestones
+                .into_iter()
+                .map(|(name, micros)| (name, std::time::Duration::from_micros(micros)))
+                .collect(),
+        })
+    }
+}
+
 fn main() {
     token_matcher::test::test_actual_matcher_implementations();
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn perf_timer_round_trips_through_json() {
+        let mut perf_timer = PerfTimer::new();
+        perf_timer.milestones = vec![
+            ("compile".into(), Duration::from_micros(120)),
+            ("match".into(), Duration::from_micros(150)),
+            ("score".into(), Duration::from_micros(400)),
+        ];
+
+        let json = serde_json::to_string(&perf_timer).unwrap();
+        assert_eq!(
+            json,
+            r#"{"milestones":[["compile",120],["match",150],["score",400]],"durations":[["compile",120],["match",30],["score",250]]}"#
+        );
+
+        let deserialized: PerfTimer = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.milestones, perf_timer.milestones);
+        assert_eq!(deserialized.durations(), perf_timer.durations());
+    }
+}
@@ -1,16 +1,42 @@
 use std::collections::BTreeSet;
+use std::ops::Range;
 
+pub mod adaptive_matcher;
 pub mod automaton_matcher;
+pub mod fielded_matcher;
+pub mod glob;
 pub mod hash_matcher;
+pub mod max_len_matcher;
+pub mod phrase_matcher;
 pub mod regex_matcher;
 pub mod regex_util;
+pub mod scoring;
+pub mod set_matcher;
+pub mod tagged_matcher;
 pub mod test_util;
 
 ///
 /// Abstraction over tantivy searcher with the functionality this module needs:
 ///
 pub trait GetDocFreq {
-    fn get_doc_freq(&self, term: &crate::Term) -> u64;
+    ///
+    /// The doc freq of a term, borrowed from the token being matched.
+    /// Use `Term` for a term that must outlive the call.
+    ///
+    fn get_doc_freq(&self, term: crate::TermRef<'_>) -> u64;
+
+    ///
+    /// Like `get_doc_freq`, but may tell that the doc freq is unknown
+    /// (e.g. the term is not in this segment) by returning `None`,
+    /// as opposed to a doc freq of 0 meaning the term truly is in no documents.
+    ///
+    /// Matchers call this method, scoring a matched token with an unknown doc freq
+    /// by the matcher's default reciprocal, if any,
+    /// while a matched token with a doc freq of 0 gets no reciprocal.
+    ///
+    fn try_get_doc_freq(&self, term: crate::TermRef<'_>) -> Option<u64> {
+        Some(self.get_doc_freq(term))
+    }
 }
 
 ///
@@ -33,6 +59,30 @@ impl DocFreqReciprocal {
             Some(DocFreqReciprocal(1.0 / (doc_freq as f32 + 1.0)))
         }
     }
+
+    ///
+    /// Like `from_doc_freq`, but an unknown doc freq yields the default reciprocal.
+    ///
+    fn from_try_doc_freq(
+        doc_freq: Option<u64>,
+        default_reciprocal: &Option<DocFreqReciprocal>,
+    ) -> Option<DocFreqReciprocal> {
+        match doc_freq {
+            Some(doc_freq) => Self::from_doc_freq(doc_freq),
+            None => default_reciprocal.clone(),
+        }
+    }
+
+    ///
+    /// The reciprocal scaled from (0, 0.5] to (0, 1], for callers expecting a relevance in [0, 1]:
+    ///
+    /// A doc freq of 1 yields 1
+    /// A doc freq of 2 yields 2/3
+    /// A doc freq of n yields 2/(n + 1)
+    ///
+    pub fn normalized_score(&self) -> f32 {
+        2.0 * self.0
+    }
 }
 
 #[cfg(test)]
@@ -42,13 +92,47 @@ impl PartialEq<DocFreqReciprocal> for DocFreqReciprocal {
     }
 }
 
+///
+/// Usage counters maintained by each matcher.
+///
+/// These are plain integers updated in place while looking up tokens,
+/// so they cost next to nothing when nobody reads them.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    /// Number of tokens looked up
+    pub lookups: u64,
+    /// Number of tokens that matched at least one predicate
+    pub matches: u64,
+    /// Matches where the doc freq reciprocal was already known
+    pub cache_hits: u64,
+    /// Matches where the doc freq had to be looked up
+    pub cache_misses: u64,
+    /// Number of calls to `GetDocFreq::try_get_doc_freq`
+    pub get_doc_freq_calls: u64,
+}
+
+///
+/// How a term predicate matches tokens:
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum TermSemantics {
+    /// The token must be equal to the term
+    Exact,
+    /// The token must start with the term
+    Prefix,
+}
+
 ///
 /// All things a token matcher can match for:
 ///
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub enum MatchPredicate {
-    Term(String),
+    Term(String, TermSemantics),
     Pattern(crate::PatternAST),
+    /// A regex expression matching the whole token, for what patterns can't express.
+    /// See `regex_util::required_features` for which backends support it.
+    Raw(String),
 }
 
 ///
@@ -56,6 +140,15 @@ pub enum MatchPredicate {
 ///
 pub type MatchPredicateSet = BTreeSet<MatchPredicate>;
 
+///
+/// Reference to one predicate of a matcher, by its position in the predicate set.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PredicateRef<'a> {
+    pub index: usize,
+    pub predicate: &'a MatchPredicate,
+}
+
 ///
 /// Trait for the external API of the matcher itself, that snippet generators and highlighters use.
 ///
@@ -70,6 +163,48 @@ pub trait LookupDocFreqReciprocal {
         token_text: &str,
         get_doc_freq: &impl GetDocFreq,
     ) -> Option<DocFreqReciprocal>;
+
+    ///
+    /// Look up a token given as bytes, e.g. a slice into a memory-mapped token file.
+    ///
+    /// The bytes are not copied, and tokens that are not valid UTF-8 never match.
+    /// No matcher allocates for a token that does not match.
+    ///
+    fn lookup_doc_freq_reciprocal_bytes(
+        &mut self,
+        token_bytes: &[u8],
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        let token_text = std::str::from_utf8(token_bytes).ok()?;
+        self.lookup_doc_freq_reciprocal(token_text, get_doc_freq)
+    }
+
+    ///
+    /// The `k` highest scoring snippet fragments of a tokenized document, best first,
+    /// each being a window of `window` tokens, see `scoring::rank_fragments`.
+    ///
+    /// `tokens` are the text and text range of every token of the document, in order.
+    ///
+    fn top_fragments(
+        &mut self,
+        tokens: &[(&str, Range<usize>)],
+        window: usize,
+        k: usize,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Vec<scoring::ScoredFragment> {
+        let matches: Vec<(usize, DocFreqReciprocal)> = tokens
+            .iter()
+            .enumerate()
+            .filter_map(|(position, (token_text, _))| {
+                self.lookup_doc_freq_reciprocal(token_text, get_doc_freq)
+                    .map(|doc_freq_reciprocal| (position, doc_freq_reciprocal))
+            })
+            .collect();
+        let token_ranges: Vec<Range<usize>> =
+            tokens.iter().map(|(_, range)| range.clone()).collect();
+
+        scoring::rank_fragments(&token_ranges, &matches, window, k)
+    }
 }
 
 pub mod test {
@@ -77,11 +212,12 @@ pub mod test {
 
     use crate::PerfTimer;
     use std::collections::HashMap;
+    use test_util::AnyTermDb;
 
     struct FooBarBazTermDb;
 
     impl GetDocFreq for FooBarBazTermDb {
-        fn get_doc_freq(&self, term: &crate::Term) -> u64 {
+        fn get_doc_freq(&self, term: crate::TermRef<'_>) -> u64 {
             match term.text() {
                 "foo" => 1,
                 "bar" => 2,
@@ -91,14 +227,6 @@ pub mod test {
         }
     }
 
-    struct AnyTermDb;
-
-    impl GetDocFreq for AnyTermDb {
-        fn get_doc_freq(&self, _: &crate::Term) -> u64 {
-            1
-        }
-    }
-
     fn assert_matcher_matches(
         matcher: &mut impl LookupDocFreqReciprocal,
         matcher_name: &str,
@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+
+use super::*;
+
+///
+/// Which of the matchers of an `AdaptiveMatcher` is in use.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AdaptiveStrategy {
+    /// Most tokens don't match: use the matcher that is cheapest at rejecting tokens
+    LowHitRate,
+    /// Most tokens match: use the matcher that is cheapest at scoring matches
+    HighHitRate,
+}
+
+///
+/// When an `AdaptiveMatcher` swaps strategy.
+///
+/// The thresholds are apart to avoid swapping back and forth around a single hit rate.
+///
+#[derive(Clone, Debug)]
+pub struct AdaptiveConfig {
+    /// The number of most recent lookups the hit rate is computed over
+    pub window_size: usize,
+    /// Swap to `HighHitRate` when the hit rate reaches this
+    pub high_hit_rate: f32,
+    /// Swap to `LowHitRate` when the hit rate falls to this
+    pub low_hit_rate: f32,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 1000,
+            high_hit_rate: 0.5,
+            low_hit_rate: 0.2,
+        }
+    }
+}
+
+///
+/// Matcher switching between two matchers for the same predicate set,
+/// based on the hit rate over a sliding window of lookups.
+/// A lookup yielding a doc freq reciprocal counts as a hit.
+///
+/// E.g. an `AutomatonMatcher` rejects non-matching tokens in one cheap DFA pass,
+/// while a `SetMatcher` attributes every match exactly.
+///
+/// Swap cost: both matchers are compiled up front, so swapping itself is free,
+/// at the price of compiling and holding two matchers.
+/// Each matcher keeps its own doc freq cache, so the first lookups after a swap
+/// may call `GetDocFreq` for tokens the other matcher already had cached.
+///
+pub struct AdaptiveMatcher<L, H> {
+    low_hit_rate_matcher: L,
+    high_hit_rate_matcher: H,
+    config: AdaptiveConfig,
+    strategy: AdaptiveStrategy,
+
+    // Hit or miss of the most recent lookups, oldest first
+    window: VecDeque<bool>,
+    window_hits: usize,
+}
+
+impl<L: LookupDocFreqReciprocal, H: LookupDocFreqReciprocal> AdaptiveMatcher<L, H> {
+    ///
+    /// Create a matcher starting out with the `LowHitRate` strategy.
+    ///
+    pub fn new(low_hit_rate_matcher: L, high_hit_rate_matcher: H, config: AdaptiveConfig) -> Self {
+        Self {
+            low_hit_rate_matcher,
+            high_hit_rate_matcher,
+            window: VecDeque::with_capacity(config.window_size + 1),
+            config,
+            strategy: AdaptiveStrategy::LowHitRate,
+            window_hits: 0,
+        }
+    }
+
+    pub fn strategy(&self) -> AdaptiveStrategy {
+        self.strategy
+    }
+
+    pub fn low_hit_rate_matcher(&self) -> &L {
+        &self.low_hit_rate_matcher
+    }
+
+    pub fn high_hit_rate_matcher(&self) -> &H {
+        &self.high_hit_rate_matcher
+    }
+
+    fn observe(&mut self, hit: bool) {
+        self.window.push_back(hit);
+        if hit {
+            self.window_hits += 1;
+        }
+        if self.window.len() > self.config.window_size {
+            if let Some(true) = self.window.pop_front() {
+                self.window_hits -= 1;
+            }
+        }
+
+        // Don't judge by a partially filled window:
+        if self.window.len() < self.config.window_size {
+            return;
+        }
+
+        let hit_rate = self.window_hits as f32 / self.window.len() as f32;
+
+        match self.strategy {
+            AdaptiveStrategy::LowHitRate if hit_rate >= self.config.high_hit_rate => {
+                self.strategy = AdaptiveStrategy::HighHitRate;
+            }
+            AdaptiveStrategy::HighHitRate if hit_rate <= self.config.low_hit_rate => {
+                self.strategy = AdaptiveStrategy::LowHitRate;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<L: LookupDocFreqReciprocal, H: LookupDocFreqReciprocal> LookupDocFreqReciprocal
+    for AdaptiveMatcher<L, H>
+{
+    fn lookup_doc_freq_reciprocal(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        let doc_freq_reciprocal = match self.strategy {
+            AdaptiveStrategy::LowHitRate => self
+                .low_hit_rate_matcher
+                .lookup_doc_freq_reciprocal(token_text, get_doc_freq),
+            AdaptiveStrategy::HighHitRate => self
+                .high_hit_rate_matcher
+                .lookup_doc_freq_reciprocal(token_text, get_doc_freq),
+        };
+
+        self.observe(doc_freq_reciprocal.is_some());
+
+        doc_freq_reciprocal
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn swaps_strategy_with_hit_rate_and_matches_correctly_throughout() {
+        let patterns: &[&[&str]] = &[&["foo"], &["ba", "*"]];
+        let mut matcher = AdaptiveMatcher::new(
+            automaton_matcher::test::test_automaton_matcher(patterns),
+            set_matcher::test::test_set_matcher(patterns),
+            AdaptiveConfig {
+                window_size: 10,
+                high_hit_rate: 0.5,
+                low_hit_rate: 0.2,
+            },
+        );
+
+        let mut lookup_stream = |tokens: &[String]| {
+            for token in tokens {
+                let expected = token == "foo" || token.starts_with("ba");
+                assert_eq!(
+                    test_util::matches(&mut matcher, token),
+                    expected,
+                    "{}",
+                    token
+                );
+            }
+            matcher.strategy()
+        };
+
+        // Low hit rate: every tenth token matches
+        let low_hit_rate_tokens: Vec<String> = (0..50)
+            .map(|i| {
+                if i % 10 == 0 {
+                    format!("bar{}", i)
+                } else {
+                    format!("qux{}", i)
+                }
+            })
+            .collect();
+        // High hit rate: every token but every tenth matches
+        let high_hit_rate_tokens: Vec<String> = (0..50)
+            .map(|i| {
+                if i % 10 == 0 {
+                    format!("qux{}", i)
+                } else if i % 2 == 0 {
+                    "foo".to_string()
+                } else {
+                    format!("baz{}", i)
+                }
+            })
+            .collect();
+
+        assert_eq!(
+            lookup_stream(&low_hit_rate_tokens),
+            AdaptiveStrategy::LowHitRate
+        );
+        assert_eq!(
+            lookup_stream(&high_hit_rate_tokens),
+            AdaptiveStrategy::HighHitRate
+        );
+        assert_eq!(
+            lookup_stream(&low_hit_rate_tokens),
+            AdaptiveStrategy::LowHitRate
+        );
+
+        assert!(matcher.low_hit_rate_matcher().metrics().lookups > 50);
+        assert!(matcher.high_hit_rate_matcher().metrics().lookups > 0);
+    }
+}
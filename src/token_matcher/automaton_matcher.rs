@@ -1,11 +1,19 @@
 use regex_automata::dense::DenseDFA;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
 
+use fixedbitset::FixedBitSet;
 use regex_automata::DFA;
 
 use crate::PatternASTNode;
 
+use super::regex_util::{
+    char_class_regex_expr, node_to_regex_expr, raw_exprs_required_features,
+    shared_prefix_regex_expr, shared_suffix_regex_expr, split_wide_terms, CompileOptions,
+    GroupedPatterns, ASCII_WILDCARD_EXPR, ASCII_WILDCARD_MAX_CHAR, WILDCARD_EXPR,
+    WILDCARD_MAX_CHAR,
+};
 use super::*;
 
 ///
@@ -15,43 +23,270 @@ use super::*;
 ///
 pub struct Automaton {
     dense_dfa: DenseDFA<Vec<usize>, usize>,
+    ascii: bool,
+}
+
+impl Automaton {
+    ///
+    /// Whether the automaton was built for an ASCII alphabet, see `CompileOptions::ascii_wildcards`.
+    ///
+    pub fn is_ascii(&self) -> bool {
+        self.ascii
+    }
+
+    ///
+    /// Self-test a freshly built automaton, e.g. before serializing it:
+    /// every sample token must match (as a whole) exactly when its expected value is `true`.
+    ///
+    /// This catches builder regressions, which would otherwise go unnoticed
+    /// until the serialized automaton is used.
+    ///
+    pub fn verify(&self, sample_tokens: &[&str], expected: &[bool]) -> Result<(), String> {
+        if sample_tokens.len() != expected.len() {
+            return Err(format!(
+                "verify: {} sample tokens, but {} expected results",
+                sample_tokens.len(),
+                expected.len()
+            ));
+        }
+
+        let unexpected: Vec<String> = sample_tokens
+            .iter()
+            .zip(expected)
+            .filter(|(token_text, expected)| self.matches_whole(token_text) != **expected)
+            .map(|(token_text, expected)| {
+                format!("{:?} (expected match: {})", token_text, expected)
+            })
+            .collect();
+
+        if !unexpected.is_empty() {
+            return Err(format!("verify failed for {}", unexpected.join(", ")));
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// The state to start matching a token in, for callers stepping the automaton themselves,
+    /// e.g. to pause and resume matching across buffer boundaries.
+    ///
+    pub fn start_state(&self) -> AutomatonState {
+        AutomatonState(self.dense_dfa.start_state())
+    }
+
+    ///
+    /// Feed one byte of the token, returning the next state,
+    /// and whether the bytes so far match as a whole.
+    ///
+    pub fn step(&self, state: AutomatonState, byte: u8) -> (AutomatonState, bool) {
+        let next_state = self.dense_dfa.next_state(state.0, byte);
+
+        (
+            AutomatonState(next_state),
+            self.dense_dfa.is_match_state(next_state),
+        )
+    }
+
+    ///
+    /// Whether the bytes fed so far match as a whole, e.g. for the start state and the empty token.
+    ///
+    pub fn is_match_state(&self, state: AutomatonState) -> bool {
+        self.dense_dfa.is_match_state(state.0)
+    }
+
+    ///
+    /// Whether no continuation of the bytes fed so far can match, so stepping further is pointless.
+    ///
+    pub fn is_dead_state(&self, state: AutomatonState) -> bool {
+        self.dense_dfa.is_dead_state(state.0)
+    }
+
+    ///
+    /// Start matching a token given in byte chunks, see `ChunkedMatch`.
+    ///
+    pub fn chunked_match(&self) -> ChunkedMatch<'_> {
+        ChunkedMatch {
+            automaton: self,
+            state: self.start_state(),
+            byte_count: 0,
+        }
+    }
+
+    ///
+    /// Whether a token given as byte chunks matches as a whole,
+    /// without buffering the token. Stops pulling chunks as soon as no match is possible.
+    ///
+    pub fn matches_chunks<'c>(&self, chunks: impl IntoIterator<Item = &'c [u8]>) -> bool {
+        let mut chunked_match = self.chunked_match();
+
+        for chunk in chunks {
+            if !chunked_match.feed(chunk) {
+                return false;
+            }
+        }
+
+        chunked_match.is_match()
+    }
+
+    fn matches_whole(&self, token_text: &str) -> bool {
+        self.dense_dfa.find(token_text.as_bytes()) == Some(token_text.len())
+    }
+}
+
+///
+/// A state of an `Automaton`, only meaningful to the automaton it came from.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AutomatonState(usize);
+
+///
+/// Incremental matching of a token arriving in byte chunks, e.g. from a decompressor,
+/// feeding each byte to the automaton's transition function as it arrives.
+///
+/// After each chunk, `is_match` tells whether the bytes so far match as a whole,
+/// i.e. the automaton is in an accepting state.
+///
+/// The regex backend has nothing like this: `regex::Regex` needs the whole token in one buffer.
+///
+pub struct ChunkedMatch<'a> {
+    automaton: &'a Automaton,
+    state: AutomatonState,
+    byte_count: usize,
+}
+
+impl<'a> ChunkedMatch<'a> {
+    ///
+    /// Feed the next chunk of the token.
+    /// Returns `false` when no continuation of the token can match, so the rest can be skipped.
+    ///
+    pub fn feed(&mut self, chunk: &[u8]) -> bool {
+        for byte in chunk {
+            if self.automaton.is_dead_state(self.state) {
+                return false;
+            }
+            self.state = self.automaton.step(self.state, *byte).0;
+            self.byte_count += 1;
+        }
+
+        !self.automaton.is_dead_state(self.state)
+    }
+
+    ///
+    /// Whether all bytes fed so far match as a whole.
+    ///
+    pub fn is_match(&self) -> bool {
+        self.automaton.is_match_state(self.state)
+    }
+
+    pub fn byte_count(&self) -> usize {
+        self.byte_count
+    }
 }
 
 pub struct AutomatonMatcher {
     automaton: Arc<Automaton>,
+    // The doc freqs of term predicates, as given by the doc freq map
+    term_doc_freq_reciprocals: HashMap<String, Option<DocFreqReciprocal>>,
     doc_freq_cache: HashMap<String, Option<DocFreqReciprocal>>,
-    term_buf: crate::Term,
+    default_reciprocal: Option<DocFreqReciprocal>,
+
+    metrics: Metrics,
 }
 
 impl AutomatonMatcher {
-    pub fn new(
+    ///
+    /// Create a matcher, where `predicates` is the predicate set (or sorted slice)
+    /// the automaton was compiled from.
+    ///
+    pub fn new<'a>(
         automaton: Arc<Automaton>,
-        predicate_set: &MatchPredicateSet,
-        term_doc_freq_reciprocals: &HashMap<String, DocFreqReciprocal>,
+        predicates: impl IntoIterator<Item = &'a MatchPredicate>,
+        term_doc_freq_reciprocals_map: &HashMap<String, DocFreqReciprocal>,
     ) -> Self {
-        let mut doc_freq_cache: HashMap<String, Option<DocFreqReciprocal>> = HashMap::new();
+        let mut term_doc_freq_reciprocals: HashMap<String, Option<DocFreqReciprocal>> =
+            HashMap::new();
 
-        for match_predicate in predicate_set {
-            if let MatchPredicate::Term(term_text) = match_predicate {
-                doc_freq_cache.insert(
+        for match_predicate in predicates {
+            if let MatchPredicate::Term(term_text, TermSemantics::Exact) = match_predicate {
+                term_doc_freq_reciprocals.insert(
                     term_text.to_string(),
-                    term_doc_freq_reciprocals
-                        .get(term_text)
-                        .map(|dfr| dfr.clone()),
+                    term_doc_freq_reciprocals_map.get(term_text).cloned(),
                 );
             }
         }
 
         Self {
             automaton,
-            doc_freq_cache,
-            term_buf: crate::Term::default(),
+            term_doc_freq_reciprocals,
+            doc_freq_cache: HashMap::new(),
+            default_reciprocal: None,
+            metrics: Metrics::default(),
         }
     }
 
-    fn text_term(&mut self, token_text: &str) -> &crate::Term {
-        self.term_buf.set_text(token_text);
-        &self.term_buf
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    ///
+    /// Set the reciprocal of matched tokens with an unknown doc freq,
+    /// including term predicates missing from the doc freq map,
+    /// see `RegexMatcher::set_default_reciprocal`.
+    ///
+    pub fn set_default_reciprocal(&mut self, default_reciprocal: Option<DocFreqReciprocal>) {
+        self.default_reciprocal = default_reciprocal;
+    }
+
+    ///
+    /// Forget the cached doc freq of a token, see `RegexMatcher::invalidate_term`.
+    /// Term predicates are not affected.
+    ///
+    pub fn invalidate_term(&mut self, term_text: &str) {
+        self.doc_freq_cache.remove(term_text);
+    }
+
+    ///
+    /// Inherit the doc freqs cached by another automaton matcher, see `RegexMatcher::transfer_cache`.
+    ///
+    pub fn transfer_cache(&mut self, from: &Self) {
+        for (token_text, doc_freq_reciprocal) in &from.doc_freq_cache {
+            self.doc_freq_cache
+                .entry(token_text.clone())
+                .or_insert_with(|| doc_freq_reciprocal.clone());
+        }
+    }
+
+    ///
+    /// Whether each token matches, one bit per token,
+    /// for callers not interested in doc freqs: this never calls `GetDocFreq`.
+    ///
+    pub fn match_mask(&mut self, tokens: &[&str]) -> FixedBitSet {
+        let mut mask = FixedBitSet::with_capacity(tokens.len());
+
+        for (index, token_text) in tokens.iter().enumerate() {
+            self.metrics.lookups += 1;
+            if self.automaton.matches_whole(token_text) {
+                self.metrics.matches += 1;
+                mask.insert(index);
+            }
+        }
+
+        mask
+    }
+
+    ///
+    /// Whether a predicate matches the token as a whole (`Some(true)`),
+    /// only a prefix of it (`Some(false)`), or neither (`None`),
+    /// the same as `RegexMatcher::is_full_match`.
+    ///
+    /// A prefix matches e.g. when the token continues with characters the wildcard does not match.
+    /// The automaton is anchored at the start of the token and finds the longest match,
+    /// which is exactly the longest matching prefix.
+    ///
+    pub fn is_full_match(&self, token_text: &str) -> Option<bool> {
+        let match_length = self.automaton.dense_dfa.find(token_text.as_bytes())?;
+
+        Some(match_length == token_text.len())
     }
 }
 
@@ -61,18 +296,36 @@ impl LookupDocFreqReciprocal for AutomatonMatcher {
         token_text: &str,
         get_doc_freq: &impl GetDocFreq,
     ) -> Option<DocFreqReciprocal> {
+        self.metrics.lookups += 1;
+
         let match_length = self.automaton.dense_dfa.find(token_text.as_bytes())?;
         if match_length < token_text.len() {
             return None;
         }
 
+        self.metrics.matches += 1;
+
         // We got a match, now need to find doc_freq:
+        if let Some(doc_freq_reciprocal) = self.term_doc_freq_reciprocals.get(token_text) {
+            self.metrics.cache_hits += 1;
+            // A term missing from the doc freq map has an unknown doc freq:
+            return doc_freq_reciprocal
+                .clone()
+                .or_else(|| self.default_reciprocal.clone());
+        }
+
         if let Some(doc_freq_reciprocal) = self.doc_freq_cache.get(token_text) {
+            self.metrics.cache_hits += 1;
             return doc_freq_reciprocal.clone();
         }
 
-        let term = self.text_term(token_text);
-        let doc_freq_reciprocal = DocFreqReciprocal::from_doc_freq(get_doc_freq.get_doc_freq(term));
+        self.metrics.cache_misses += 1;
+        self.metrics.get_doc_freq_calls += 1;
+
+        let doc_freq_reciprocal = DocFreqReciprocal::from_try_doc_freq(
+            get_doc_freq.try_get_doc_freq(crate::TermRef::from(token_text)),
+            &self.default_reciprocal,
+        );
 
         self.doc_freq_cache
             .insert(token_text.to_string(), doc_freq_reciprocal.clone());
@@ -81,42 +334,141 @@ impl LookupDocFreqReciprocal for AutomatonMatcher {
     }
 }
 
-const WILDCARD_EXPR: &str = r#"[\x{0000}-\x{024f}]*"#;
-
 pub fn compile_automaton(predicate_set: &MatchPredicateSet) -> Result<Arc<Automaton>, String> {
-    let regex_pattern = generate_regex_pattern(predicate_set, WILDCARD_EXPR);
+    compile_automaton_from_groups(&GroupedPatterns::group(predicate_set))
+}
+
+///
+/// Compile predicates that are already sorted and deduplicated, see `GroupedPatterns::group_sorted`.
+///
+pub fn compile_automaton_sorted(predicates: &[MatchPredicate]) -> Result<Arc<Automaton>, String> {
+    compile_automaton_from_groups(&GroupedPatterns::group_sorted(predicates))
+}
 
+///
+/// Compile an already grouped predicate set,
+/// allowing the caller to preprocess the groups before the pattern is generated.
+///
+pub fn compile_automaton_from_groups(groups: &GroupedPatterns) -> Result<Arc<Automaton>, String> {
+    compile_automaton_from_groups_with_wildcards(groups, false)
+}
+
+fn compile_automaton_from_groups_with_wildcards(
+    groups: &GroupedPatterns,
+    ascii_wildcards: bool,
+) -> Result<Arc<Automaton>, String> {
+    raw_exprs_required_features(groups.raw.iter().copied())
+        .check_dfa_support("compile_automaton")?;
+
+    let (wildcard_expr, ascii) = if ascii_wildcards {
+        (ASCII_WILDCARD_EXPR, groups.is_ascii())
+    } else {
+        (WILDCARD_EXPR, false)
+    };
+
+    let regex_pattern = generate_regex_pattern_from_groups(groups, wildcard_expr);
+
+    build_automaton(&regex_pattern, ascii)
+}
+
+///
+/// Compile the automaton while reporting progress as a percentage (0-100),
+/// which another thread may poll, e.g. to show a progress bar.
+///
+/// The DFA build itself reports no intermediate progress, so the percentage
+/// stays put during that (slow) step and reaches 100 when compilation is done.
+/// On failure, the progress is left where the failing step started.
+///
+pub fn compile_automaton_with_progress(
+    predicate_set: &MatchPredicateSet,
+    progress: Arc<AtomicU8>,
+) -> Result<Arc<Automaton>, String> {
+    progress.store(0, Ordering::Relaxed);
+
+    let groups = GroupedPatterns::group(predicate_set);
+    raw_exprs_required_features(groups.raw.iter().copied())
+        .check_dfa_support("compile_automaton")?;
+    progress.store(5, Ordering::Relaxed);
+
+    let regex_pattern = generate_regex_pattern_from_groups(&groups, WILDCARD_EXPR);
+    progress.store(10, Ordering::Relaxed);
+
+    let automaton = build_automaton(&regex_pattern, false)?;
+    progress.store(100, Ordering::Relaxed);
+
+    Ok(automaton)
+}
+
+///
+/// Compile an automaton, first checking the predicate set against the limits of the options.
+///
+pub fn compile_automaton_with_options(
+    predicate_set: &MatchPredicateSet,
+    options: &CompileOptions,
+) -> Result<Arc<Automaton>, String> {
+    options
+        .check(predicate_set)
+        .map_err(|error| format!("compile_automaton: {}", error))?;
+
+    compile_automaton_from_groups_with_wildcards(
+        &GroupedPatterns::group(predicate_set),
+        options.ascii_wildcards,
+    )
+}
+
+///
+/// Build the DFA for a generated pattern.
+/// With `ascii`, the pattern must only match ASCII, and Unicode support is left out of the DFA.
+///
+fn build_automaton(regex_pattern: &str, ascii: bool) -> Result<Arc<Automaton>, String> {
     println!("au pattern: {}", regex_pattern);
 
     // CPU usage alert:
+    // Longest match semantics, because a full-token match must not be cut short
+    // by a higher priority alternative matching only a prefix of the token:
     let dense_dfa = regex_automata::dense::Builder::new()
         .anchored(true)
-        .build(&regex_pattern)
+        .longest_match(true)
+        .unicode(!ascii)
+        .build(regex_pattern)
         .map_err(|error| format!("compile_automaton failed. {:?}", error))?;
 
-    Ok(Arc::new(Automaton { dense_dfa }))
+    Ok(Arc::new(Automaton { dense_dfa, ascii }))
 }
 
 fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_expr: &str) -> String {
-    let groups = super::regex_util::GroupedPatterns::group(predicate_set);
+    generate_regex_pattern_from_groups(&GroupedPatterns::group(predicate_set), wildcard_expr)
+}
+
+fn generate_regex_pattern_from_groups(groups: &GroupedPatterns, wildcard_expr: &str) -> String {
+    // Wide alternations of terms have their single character terms in a class instead:
+    let (term_alternatives, term_chars) = split_wide_terms(&groups.terms);
 
     let regex_exprs: Vec<Option<String>> = vec![
-        if groups.terms.len() > 0 {
+        if !term_alternatives.is_empty() {
             Some(
-                groups
-                    .terms
-                    .into_iter()
-                    .map(regex_syntax::escape)
+                term_alternatives
+                    .iter()
+                    .map(|term| regex_syntax::escape(term))
                     .collect::<Vec<_>>()
                     .join("|"),
             )
         } else {
             None
         },
-        if groups.terms_wc.len() > 0 {
+        if !term_chars.is_empty() {
+            Some(char_class_regex_expr(&term_chars))
+        } else {
+            None
+        },
+        if groups.prefix_terms.len() + groups.terms_wc.len() > 0 {
             Some(format!(
                 "(({}){})",
-                pattern_asts_to_regex_string(&groups.terms_wc, wildcard_expr),
+                shared_prefix_asts_to_regex_string(
+                    &groups.prefix_terms,
+                    &groups.terms_wc,
+                    wildcard_expr
+                ),
                 wildcard_expr,
             ))
         } else {
@@ -134,7 +486,7 @@ fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_exp
             Some(format!(
                 "({}({}))",
                 wildcard_expr,
-                pattern_asts_to_regex_string(&groups.wc_terms, wildcard_expr)
+                shared_suffix_asts_to_regex_string(&groups.wc_terms, wildcard_expr)
             ))
         } else {
             None
@@ -149,6 +501,23 @@ fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_exp
         } else {
             None
         },
+        if !groups.raw.is_empty() {
+            Some(
+                groups
+                    .raw
+                    .iter()
+                    .map(|expr| format!("(?:{})", expr))
+                    .collect::<Vec<_>>()
+                    .join("|"),
+            )
+        } else {
+            None
+        },
+        if groups.match_all {
+            Some(wildcard_expr.to_string())
+        } else {
+            None
+        },
     ];
 
     regex_exprs
@@ -158,25 +527,102 @@ fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_exp
         .join("|")
 }
 
+///
+/// Like `pattern_asts_to_regex_string` for patterns that are followed by a wildcard,
+/// but prefix terms and patterns that are plain literals are compacted into a prefix trie.
+///
+fn shared_prefix_asts_to_regex_string(
+    prefix_terms: &[&str],
+    pattern_asts: &[&[PatternASTNode]],
+    wildcard_expr: &str,
+) -> String {
+    let mut literals: Vec<&str> = prefix_terms.to_vec();
+    let mut other_asts: Vec<&[PatternASTNode]> = vec![];
+
+    for ast_nodes in pattern_asts {
+        match ast_nodes {
+            [PatternASTNode::Literal(text)] => literals.push(text),
+            _ => other_asts.push(ast_nodes),
+        }
+    }
+
+    let regex_exprs: Vec<Option<String>> = vec![
+        if literals.is_empty() {
+            None
+        } else {
+            // Only the ASCII wildcard is narrower than the default one:
+            let wildcard_max_char = if wildcard_expr == ASCII_WILDCARD_EXPR {
+                ASCII_WILDCARD_MAX_CHAR
+            } else {
+                WILDCARD_MAX_CHAR
+            };
+            Some(shared_prefix_regex_expr(&literals, wildcard_max_char))
+        },
+        if other_asts.is_empty() {
+            None
+        } else {
+            Some(pattern_asts_to_regex_string(&other_asts, wildcard_expr))
+        },
+    ];
+
+    regex_exprs
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+///
+/// Like `pattern_asts_to_regex_string`, but patterns that are plain literals share common suffixes.
+///
+fn shared_suffix_asts_to_regex_string(
+    pattern_asts: &[&[PatternASTNode]],
+    wildcard_expr: &str,
+) -> String {
+    let mut literals: Vec<&str> = vec![];
+    let mut other_asts: Vec<&[PatternASTNode]> = vec![];
+
+    for ast_nodes in pattern_asts {
+        match ast_nodes {
+            [PatternASTNode::Literal(text)] => literals.push(text),
+            _ => other_asts.push(ast_nodes),
+        }
+    }
+
+    let regex_exprs: Vec<Option<String>> = vec![
+        if literals.is_empty() {
+            None
+        } else {
+            Some(shared_suffix_regex_expr(&literals))
+        },
+        if other_asts.is_empty() {
+            None
+        } else {
+            Some(pattern_asts_to_regex_string(&other_asts, wildcard_expr))
+        },
+    ];
+
+    regex_exprs
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
 fn pattern_asts_to_regex_string(pattern_asts: &[&[PatternASTNode]], wildcard_expr: &str) -> String {
     pattern_asts
         .into_iter()
         .map(|ast_nodes| match ast_nodes.len() {
             0 => None,
             1 => match ast_nodes.first() {
-                Some(PatternASTNode::Literal(text)) => Some(regex_syntax::escape(text)),
-                _ => None,
+                Some(PatternASTNode::Wildcard) | None => None,
+                Some(node) => Some(node_to_regex_expr(node, wildcard_expr)),
             },
             _ => Some(format!(
                 "({})",
                 ast_nodes
                     .into_iter()
-                    .map(|node| {
-                        match node {
-                            PatternASTNode::Literal(text) => regex_syntax::escape(text),
-                            PatternASTNode::Wildcard => wildcard_expr.to_string(),
-                        }
-                    })
+                    .map(|node| node_to_regex_expr(node, wildcard_expr))
                     .collect::<Vec<_>>()
                     .join("")
             )),
@@ -206,6 +652,119 @@ pub mod test {
         generate_regex_pattern(&test_util::create_predicate_set(patterns), ".*")
     }
 
+    #[test]
+    fn match_mask_agrees_with_lookups() {
+        let mut matcher =
+            test_automaton_matcher(&[&["foo"], &["ba", "*"], &["*", "qux"], &["[1-9]"]]);
+        let tokens = ["foo", "fo", "bar", "b", "aqux", "quxa", "5", "10", ""];
+
+        let mask = matcher.match_mask(&tokens);
+
+        assert_eq!(mask.len(), tokens.len());
+        for (index, token) in tokens.iter().enumerate() {
+            assert_eq!(
+                mask.contains(index),
+                test_util::matches(&mut matcher, token),
+                "{}",
+                token
+            );
+        }
+        assert_eq!(mask.count_ones(..), 4);
+    }
+
+    #[test]
+    fn invalidated_term_is_looked_up_again() {
+        let mut matcher = test_automaton_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        for _ in 0..2 {
+            matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+            matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        }
+        assert_eq!(term_db.calls(), 2);
+
+        matcher.invalidate_term("foo1");
+
+        matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn transferred_cache_saves_doc_freq_calls_after_rebuild() {
+        let mut old_matcher = test_automaton_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        old_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        old_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        let mut new_matcher = test_automaton_matcher(&[&["foo", "*"], &["*", "bar"]]);
+        new_matcher.transfer_cache(&old_matcher);
+
+        new_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        new_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        new_matcher.lookup_doc_freq_reciprocal("xbar", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn invalidating_a_term_predicate_keeps_its_mapped_doc_freq() {
+        let mut matcher = test_automaton_matcher(&[&["foo"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        matcher.invalidate_term("foo");
+
+        assert!(matcher
+            .lookup_doc_freq_reciprocal("foo", &term_db)
+            .is_some());
+        assert_eq!(term_db.calls(), 0);
+    }
+
+    #[test]
+    fn matched_tokens_with_unknown_doc_freq_get_the_default_reciprocal() {
+        let mut matcher = test_automaton_matcher(&[&["foo", "*"]]);
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        // Also for the cached doc freq:
+        for _ in 0..2 {
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.1))
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo0", &test_util::SegmentTermDb),
+                None
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo1", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.5))
+            );
+        }
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("bar?", &test_util::SegmentTermDb),
+            None
+        );
+    }
+
+    #[test]
+    fn terms_missing_from_the_doc_freq_map_get_the_default_reciprocal() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo?"]]);
+        let mut matcher = AutomatonMatcher::new(
+            compile_automaton(&predicate_set).unwrap(),
+            &predicate_set,
+            &HashMap::new(),
+        );
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+            Some(DocFreqReciprocal(0.1))
+        );
+    }
+
     #[test]
     fn generate_regex_pattern_works_with_empty_input() {
         assert_eq!(test_generate_regex_pattern(&[]), "".to_string());
@@ -223,7 +782,7 @@ pub mod test {
     fn generate_regex_pattern_works_with_every_pattern_end_wildcarded() {
         assert_eq!(
             test_generate_regex_pattern(&[&["foo", "*"], &["bar", "*"], &["baz", "*"]]),
-            "((bar|baz|foo).*)".to_string()
+            "((ba(?:r|z)|foo).*)".to_string()
         );
     }
 
@@ -254,6 +813,472 @@ pub mod test {
         );
     }
 
+    #[test]
+    fn generate_regex_pattern_shares_suffixes_of_wc_terms() {
+        assert_eq!(
+            test_generate_regex_pattern(&[&["*", "ing"], &["*", "ring"], &["*", "bring"]]),
+            "(.*((?:(?:b)?r)?ing))".to_string()
+        );
+    }
+
+    #[test]
+    fn shared_suffixes_reduce_automaton_size() {
+        let suffixes = [
+            "ing", "ring", "bring", "string", "spring", "sing", "thing", "going", "doing", "being",
+            "seeing", "fleeing",
+        ];
+        let patterns: Vec<[&str; 2]> = suffixes.iter().map(|suffix| ["*", *suffix]).collect();
+        let patterns: Vec<&[&str]> = patterns.iter().map(|pattern| &pattern[..]).collect();
+        let predicate_set = test_util::create_predicate_set(&patterns);
+
+        let shared = compile_automaton(&predicate_set).unwrap();
+        let unshared = build_automaton(
+            &format!("({}({}))", WILDCARD_EXPR, suffixes.join("|")),
+            false,
+        )
+        .unwrap();
+
+        let (shared_size, unshared_size) = (
+            shared.dense_dfa.memory_usage(),
+            unshared.dense_dfa.memory_usage(),
+        );
+        assert!(
+            shared_size < unshared_size,
+            "shared: {}, unshared: {}",
+            shared_size,
+            unshared_size
+        );
+
+        let mut matcher = test_automaton_matcher(&patterns);
+        for token in &["bring", "ing", "singing", "fleeing", "sin", "ringo"] {
+            let expected = unshared
+                .dense_dfa
+                .find(token.as_bytes())
+                .map(|length| length == token.len())
+                .unwrap_or(false);
+            assert_eq!(
+                test_util::matches(&mut matcher, token),
+                expected,
+                "{}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn shared_prefixes_reduce_automaton_size() {
+        let prefixes = [
+            "foo", "foob", "fooba", "foobar", "bar", "bark", "barn", "barrel", "baz", "bazaar",
+            "qux", "quux", "quuux", "foo😀",
+        ];
+        let patterns: Vec<[&str; 2]> = prefixes.iter().map(|prefix| [*prefix, "*"]).collect();
+        let patterns: Vec<&[&str]> = patterns.iter().map(|pattern| &pattern[..]).collect();
+        let predicate_set = test_util::create_predicate_set(&patterns);
+
+        let shared = compile_automaton(&predicate_set).unwrap();
+        let unshared = regex_automata::dense::Builder::new()
+            .anchored(true)
+            .longest_match(true)
+            .build(&format!("(({}){})", prefixes.join("|"), WILDCARD_EXPR))
+            .unwrap();
+
+        assert!(shared.dense_dfa.memory_usage() < unshared.memory_usage());
+
+        let mut matcher = test_automaton_matcher(&patterns);
+        for token in &[
+            "foo",
+            "fo",
+            "foobarqux",
+            "barrel",
+            "bars",
+            "ba",
+            "bazaa",
+            "quuuux",
+            "qu",
+            "xfoo",
+            "foo😀x",
+        ] {
+            let expected = unshared
+                .find(token.as_bytes())
+                .map(|length| length == token.len())
+                .unwrap_or(false);
+            assert_eq!(
+                test_util::matches(&mut matcher, token),
+                expected,
+                "{}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn doc_freq_is_looked_up_for_the_token_itself() {
+        struct TokenAddressTermDb {
+            term_addresses: std::cell::RefCell<Vec<(usize, usize)>>,
+        }
+
+        impl GetDocFreq for TokenAddressTermDb {
+            fn get_doc_freq(&self, term: crate::TermRef<'_>) -> u64 {
+                assert_eq!(term.text(), "barbaz");
+                self.term_addresses
+                    .borrow_mut()
+                    .push((term.as_bytes().as_ptr() as usize, term.as_bytes().len()));
+                1
+            }
+        }
+
+        let mut matcher = test_automaton_matcher(&[&["ba", "*"]]);
+        let term_db = TokenAddressTermDb {
+            term_addresses: Default::default(),
+        };
+        let token = String::from("barbaz");
+
+        assert!(matcher
+            .lookup_doc_freq_reciprocal(&token, &term_db)
+            .is_some());
+
+        // The term borrows the token's own bytes, no copy was made:
+        assert_eq!(
+            *term_db.term_addresses.borrow(),
+            vec![(token.as_ptr() as usize, token.len())]
+        );
+    }
+
+    #[test]
+    fn stepping_the_automaton_manually_reaches_a_match_state() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["ba", "*", "z"]]);
+        let automaton = compile_automaton(&predicate_set).unwrap();
+
+        let start_state = automaton.start_state();
+        assert!(!automaton.is_match_state(start_state));
+
+        let mut state = start_state;
+        let mut matched_at = vec![];
+        for (index, byte) in b"bazz".iter().enumerate() {
+            let (next_state, is_match) = automaton.step(state, *byte);
+            assert_eq!(is_match, automaton.is_match_state(next_state));
+            if is_match {
+                matched_at.push(index + 1);
+            }
+            state = next_state;
+        }
+        assert_eq!(matched_at, vec![3, 4]);
+
+        // Paused states can be resumed later:
+        let paused = automaton.step(start_state, b'f').0;
+        let resumed = automaton.step(automaton.step(paused, b'o').0, b'o');
+        assert!(resumed.1);
+        assert!(automaton.is_dead_state(automaton.step(resumed.0, b'o').0));
+    }
+
+    #[test]
+    fn matches_token_fed_in_single_byte_chunks() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["ba", "*", "z"]]);
+        let automaton = compile_automaton(&predicate_set).unwrap();
+
+        let single_byte_chunks = |token_text: &'static str| {
+            token_text
+                .as_bytes()
+                .chunks(1)
+                .collect::<Vec<&'static [u8]>>()
+        };
+
+        for token_text in &["foo", "baz", "barbaz", "fo", "food", "bazaar", ""] {
+            assert_eq!(
+                automaton.matches_chunks(single_byte_chunks(token_text)),
+                automaton.matches_whole(token_text),
+                "{}",
+                token_text
+            );
+        }
+
+        // The match is reported as soon as the accepting state is reached:
+        let mut chunked_match = automaton.chunked_match();
+        let mut reported_at = vec![];
+        for chunk in single_byte_chunks("bazbaz") {
+            assert!(chunked_match.feed(chunk));
+            if chunked_match.is_match() {
+                reported_at.push(chunked_match.byte_count());
+            }
+        }
+        assert_eq!(reported_at, vec![3, 6]);
+
+        // Dead early, the rest of the token is not needed:
+        let mut chunked_match = automaton.chunked_match();
+        assert!(!chunked_match.feed(b"x"));
+        assert!(!chunked_match.feed(b"yz"));
+        assert!(!chunked_match.is_match());
+    }
+
+    #[test]
+    fn compact_wildcard_expr_shrinks_many_wildcard_patterns() {
+        const VERBOSE_WILDCARD_EXPR: &str = r#"[\x{0000}-\x{024f}]*"#;
+
+        let patterns: Vec<Vec<String>> = (0..50)
+            .map(|i| {
+                vec![
+                    format!("a{}", i),
+                    "*".to_string(),
+                    "b".to_string(),
+                    "*".to_string(),
+                    "c".to_string(),
+                ]
+            })
+            .collect();
+        let patterns: Vec<Vec<&str>> = patterns
+            .iter()
+            .map(|pattern| pattern.iter().map(|node| node.as_str()).collect())
+            .collect();
+        let patterns: Vec<&[&str]> = patterns.iter().map(|pattern| &pattern[..]).collect();
+        let predicate_set = test_util::create_predicate_set(&patterns);
+
+        let verbose_pattern = generate_regex_pattern(&predicate_set, VERBOSE_WILDCARD_EXPR);
+        let compact_pattern = generate_regex_pattern(&predicate_set, WILDCARD_EXPR);
+
+        // 100 wildcards, each 6 bytes shorter:
+        assert_eq!(verbose_pattern.len() - compact_pattern.len(), 100 * 6);
+
+        let verbose_automaton = build_automaton(&verbose_pattern, false).unwrap();
+        let compact_automaton = build_automaton(&compact_pattern, false).unwrap();
+        for token_text in &["a7xbyc", "a7bc", "a7\u{24f}b\u{250}c", "a50bc", "a1b", ""] {
+            assert_eq!(
+                compact_automaton.matches_whole(token_text),
+                verbose_automaton.matches_whole(token_text),
+                "{}",
+                token_text
+            );
+        }
+    }
+
+    #[test]
+    fn too_many_wildcards_per_pattern_is_an_error() {
+        let predicate_set = test_util::create_predicate_set(&[
+            &["foo"],
+            &["a", "*", "b", "*", "*", "c", "*", "d"],
+            &["a", "*", "b", "*", "c", "*", "d", "*", "e"],
+        ]);
+        let options = CompileOptions {
+            max_wildcards_per_pattern: Some(3),
+            ..CompileOptions::default()
+        };
+
+        assert_eq!(
+            compile_automaton_with_options(&predicate_set, &options).err(),
+            Some(
+                "compile_automaton: pattern a*b*c*d*e has 4 wildcards, the maximum is 3"
+                    .to_string()
+            )
+        );
+
+        // Adjacent wildcards count as one:
+        let allowed_set =
+            test_util::create_predicate_set(&[&["foo"], &["a", "*", "b", "*", "*", "c", "*", "d"]]);
+        assert!(compile_automaton_with_options(&allowed_set, &options).is_ok());
+        assert!(compile_automaton_with_options(&predicate_set, &CompileOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn ascii_wildcards_select_the_ascii_alphabet_for_ascii_literals() {
+        let options = CompileOptions {
+            ascii_wildcards: true,
+            ..CompileOptions::default()
+        };
+
+        let ascii_set = test_util::create_predicate_set(&[&["smor", "*"], &["*", "brod"]]);
+        let ascii_automaton = compile_automaton_with_options(&ascii_set, &options).unwrap();
+        assert!(ascii_automaton.is_ascii());
+        assert!(ascii_automaton.matches_whole("smorrebrod"));
+        assert!(!ascii_automaton.matches_whole("smørrebrod"));
+
+        let unicode_set = test_util::create_predicate_set(&[&["smør", "*"], &["*", "brod"]]);
+        let unicode_automaton = compile_automaton_with_options(&unicode_set, &options).unwrap();
+        assert!(!unicode_automaton.is_ascii());
+        assert!(unicode_automaton.matches_whole("smørrebrod"));
+        assert!(!unicode_automaton.matches_whole("smørrebrød"));
+
+        assert!(!compile_automaton(&ascii_set).unwrap().is_ascii());
+    }
+
+    #[test]
+    fn verify_detects_wrong_expectations() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["ba", "*"]]);
+        let automaton = compile_automaton(&predicate_set).unwrap();
+        let sample_tokens = ["foo", "bar", "fo", "qux"];
+
+        assert_eq!(
+            automaton.verify(&sample_tokens, &[true, true, false, false]),
+            Ok(())
+        );
+        assert_eq!(
+            automaton.verify(&sample_tokens, &[true, true, true, false]),
+            Err("verify failed for \"fo\" (expected match: true)".to_string())
+        );
+        assert!(automaton.verify(&sample_tokens, &[true]).is_err());
+    }
+
+    #[test]
+    fn compile_automaton_from_modified_groups() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["bar"], &["ba", "*"]]);
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+
+        let mut groups = GroupedPatterns::group(&predicate_set);
+        groups.terms.retain(|term_text| *term_text != "foo");
+
+        let mut matcher = AutomatonMatcher::new(
+            compile_automaton_from_groups(&groups).unwrap(),
+            &predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+
+        assert!(!test_util::matches(&mut matcher, "foo"));
+        assert!(test_util::matches(&mut matcher, "bar"));
+        assert!(test_util::matches(&mut matcher, "baz"));
+    }
+
+    #[test]
+    fn metrics_count_cache_hits_and_doc_freq_calls() {
+        let mut matcher = test_automaton_matcher(&[&["foo"], &["ba", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        for token in &["foo", "bar", "qux", "bar", "baz", "foo"] {
+            matcher.lookup_doc_freq_reciprocal(token, &term_db);
+        }
+
+        assert_eq!(
+            matcher.metrics(),
+            &Metrics {
+                lookups: 6,
+                matches: 5,
+                cache_hits: 3,
+                cache_misses: 2,
+                get_doc_freq_calls: 2,
+            }
+        );
+        assert_eq!(term_db.calls(), 2);
+    }
+
+    #[test]
+    fn num_range_matches_integers_within_range() {
+        let mut matcher = test_automaton_matcher(&[&["[10-20]"]]);
+
+        assert!(test_util::matches(&mut matcher, "15"));
+        assert!(!test_util::matches(&mut matcher, "5"));
+        assert!(!test_util::matches(&mut matcher, "25"));
+        assert!(!test_util::matches(&mut matcher, "150"));
+    }
+
+    #[test]
+    fn is_full_match_distinguishes_partial_matches() {
+        let matcher = test_automaton_matcher(&[&["foo"], &["ba", "*"], &["*", "qu", "*"]]);
+
+        // term
+        assert_eq!(matcher.is_full_match("foo"), Some(true));
+        assert_eq!(matcher.is_full_match("fooo"), Some(false));
+        assert_eq!(matcher.is_full_match("fo"), None);
+        // prefix
+        assert_eq!(matcher.is_full_match("bar"), Some(true));
+        assert_eq!(matcher.is_full_match("bar\u{1f600}"), Some(false));
+        // substring
+        assert_eq!(matcher.is_full_match("aqua"), Some(true));
+        assert_eq!(matcher.is_full_match("qu\u{1f600}"), Some(false));
+        assert_eq!(matcher.is_full_match("\u{1f600}qu"), None);
+        assert_eq!(matcher.is_full_match("\u{1f600}qux"), None);
+    }
+
+    #[test]
+    fn compile_automaton_sorted_matches_like_the_set_path() {
+        let predicate_set =
+            test_util::create_predicate_set(&[&["foo"], &["ba", "*"], &["*", "qux"], &["[1-9]"]]);
+        let predicates: Vec<MatchPredicate> = predicate_set.iter().cloned().collect();
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+
+        let mut set_matcher = AutomatonMatcher::new(
+            compile_automaton(&predicate_set).unwrap(),
+            &predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+        let mut sorted_matcher = AutomatonMatcher::new(
+            compile_automaton_sorted(&predicates).unwrap(),
+            &predicates,
+            &term_doc_freq_reciprocals,
+        );
+
+        for token in &["foo", "bar", "aqux", "5", "fo", "10"] {
+            assert_eq!(
+                sorted_matcher.lookup_doc_freq_reciprocal(token, &test_util::AnyTermDb),
+                set_matcher.lookup_doc_freq_reciprocal(token, &test_util::AnyTermDb),
+                "{}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn wide_single_char_term_alternation_becomes_a_class() {
+        let terms: Vec<String> = (0..3000)
+            .map(|i| std::char::from_u32(0x4e00 + i).unwrap().to_string())
+            .chain(vec!["foo".to_string()])
+            .collect();
+        let patterns: Vec<[&str; 1]> = terms.iter().map(|term| [term.as_str()]).collect();
+        let patterns: Vec<&[&str]> = patterns.iter().map(|pattern| &pattern[..]).collect();
+
+        assert_eq!(
+            test_generate_regex_pattern(&patterns),
+            "foo|[\u{4e00}-\u{59b7}]"
+        );
+
+        let mut matcher = test_automaton_matcher(&patterns);
+        assert!(test_util::matches(&mut matcher, "\u{4e00}"));
+        assert!(test_util::matches(&mut matcher, "\u{59b7}"));
+        assert!(test_util::matches(&mut matcher, "foo"));
+        assert!(!test_util::matches(&mut matcher, "\u{59b8}"));
+    }
+
+    #[test]
+    fn matches_tokens_in_a_byte_buffer() {
+        let mut matcher = test_automaton_matcher(&[&["foo"], &["ba", "*"]]);
+
+        // Simulates a memory-mapped file of newline separated tokens:
+        let mmap: &[u8] = b"foo\nbar\nqux\nba\xff\nbaz\n\xfe\n";
+
+        let matching_tokens: Vec<&[u8]> = mmap
+            .split(|byte| *byte == b'\n')
+            .filter(|token_bytes| {
+                matcher
+                    .lookup_doc_freq_reciprocal_bytes(token_bytes, &test_util::AnyTermDb)
+                    .is_some()
+            })
+            .collect();
+
+        assert_eq!(matching_tokens, vec![&b"foo"[..], &b"bar"[..], &b"baz"[..]]);
+        // Only the two tokens matching the pattern had their doc freq looked up:
+        assert_eq!(matcher.metrics().cache_misses, 2);
+    }
+
+    #[test]
+    fn compile_progress_reaches_100_on_completion() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["ba", "*"]]);
+        let progress = Arc::new(AtomicU8::new(42));
+
+        let automaton = compile_automaton_with_progress(&predicate_set, progress.clone()).unwrap();
+        let mut matcher = AutomatonMatcher::new(automaton, &predicate_set, &HashMap::new());
+
+        assert_eq!(progress.load(Ordering::Relaxed), 100);
+        assert!(test_util::matches(&mut matcher, "bar"));
+    }
+
+    #[test]
+    fn term_matching_a_prefix_does_not_cut_off_longer_match() {
+        let mut matcher = test_automaton_matcher(&[&["\u{0}"], &["\u{0}x", "*"]]);
+
+        assert!(test_util::matches(&mut matcher, "\u{0}"));
+        assert!(test_util::matches(&mut matcher, "\u{0}x"));
+        assert!(test_util::matches(&mut matcher, "\u{0}x\u{24f}"));
+        assert!(!test_util::matches(&mut matcher, "\u{0}\u{0}"));
+    }
+
     #[test]
     #[ignore = "enable this test to help analyzing automaton compile times"]
     fn test_various_dfa() {
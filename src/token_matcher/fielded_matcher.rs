@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use super::*;
+
+///
+/// Identifies a field in a multi-field search (cf. tantivy's `Field`).
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FieldId(pub u32);
+
+///
+/// Matcher with separately compiled predicates per field,
+/// so that a token is only matched against the predicates of its own field.
+///
+pub struct FieldedMatcher<M> {
+    field_matchers: HashMap<FieldId, M>,
+}
+
+impl<M> Default for FieldedMatcher<M> {
+    fn default() -> Self {
+        Self {
+            field_matchers: HashMap::new(),
+        }
+    }
+}
+
+impl<M: LookupDocFreqReciprocal> FieldedMatcher<M> {
+    ///
+    /// Compile one matcher per field with the given compile function,
+    /// e.g. one creating a `RegexMatcher` from the field's predicate set.
+    ///
+    pub fn compile(
+        field_predicate_sets: &HashMap<FieldId, MatchPredicateSet>,
+        mut compile_matcher: impl FnMut(&MatchPredicateSet) -> Result<M, String>,
+    ) -> Result<Self, String> {
+        let mut fielded_matcher = Self::default();
+
+        for (field, predicate_set) in field_predicate_sets {
+            fielded_matcher.insert_field(*field, compile_matcher(predicate_set)?);
+        }
+
+        Ok(fielded_matcher)
+    }
+
+    ///
+    /// Set the matcher of a field, returning the previous one.
+    ///
+    pub fn insert_field(&mut self, field: FieldId, matcher: M) -> Option<M> {
+        self.field_matchers.insert(field, matcher)
+    }
+
+    pub fn field_matcher(&self, field: FieldId) -> Option<&M> {
+        self.field_matchers.get(&field)
+    }
+
+    ///
+    /// Look up a token against the predicates of one field.
+    /// A field without predicates matches nothing.
+    ///
+    pub fn match_in_field(
+        &mut self,
+        field: FieldId,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        self.field_matchers
+            .get_mut(&field)?
+            .lookup_doc_freq_reciprocal(token_text, get_doc_freq)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use maplit::hashmap;
+
+    use super::regex_matcher::{compile_regex, RegexMatcher};
+
+    #[test]
+    fn tokens_only_match_predicates_of_their_field() {
+        let title = FieldId(0);
+        let body = FieldId(1);
+        let field_predicate_sets = hashmap! {
+            title => test_util::create_predicate_set(&[&["foo"], &["ba", "*"]]),
+            body => test_util::create_predicate_set(&[&["bar"], &["qux"]]),
+        };
+
+        let mut matcher = FieldedMatcher::compile(&field_predicate_sets, |predicate_set| {
+            Ok(RegexMatcher::new(
+                compile_regex(predicate_set)?,
+                predicate_set,
+                &test_util::term_doc_freq_reciprocals_from_predicate_set(predicate_set),
+            ))
+        })
+        .unwrap();
+
+        let mut matches_in_field = |field, token_text| {
+            matcher
+                .match_in_field(field, token_text, &test_util::AnyTermDb)
+                .is_some()
+        };
+
+        // "bar" is matched by both fields' predicates:
+        assert!(matches_in_field(title, "bar"));
+        assert!(matches_in_field(body, "bar"));
+
+        assert!(matches_in_field(title, "foo"));
+        assert!(!matches_in_field(body, "foo"));
+        assert!(matches_in_field(title, "baz"));
+        assert!(!matches_in_field(body, "baz"));
+        assert!(!matches_in_field(title, "qux"));
+        assert!(matches_in_field(body, "qux"));
+
+        assert!(!matches_in_field(FieldId(2), "foo"));
+    }
+}
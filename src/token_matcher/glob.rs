@@ -0,0 +1,317 @@
+use std::fmt;
+
+use crate::{PatternAST, PatternASTNode};
+
+use super::*;
+
+///
+/// Glob syntax for match predicates:
+///
+/// * `*` is a wildcard
+/// * `[min-max]` is a numeric range, e.g. `[10-20]` or `[-9--1]`
+/// * `(node){min,max}` or `(node){min,}` repeats a single node, e.g. `(ab){2,4}` or `([0-9]){3,}`
+/// * anything else is literal text, where `\`, `*`, `[`, `(`, `)`, `{` and `}` are escaped with a `\`
+///
+/// A glob consisting of literal text only denotes an exact term.
+///
+impl fmt::Display for PatternASTNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternASTNode::Literal(text) => {
+                for char in text.chars() {
+                    if matches!(char, '\\' | '*' | '[' | '(' | ')' | '{' | '}') {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", char)?;
+                }
+                Ok(())
+            }
+            PatternASTNode::Wildcard => write!(f, "*"),
+            PatternASTNode::NumRange { min, max } => write!(f, "[{}-{}]", min, max),
+            PatternASTNode::Repeat { node, min, max } => match max {
+                Some(max) => write!(f, "({}){{{},{}}}", node, min, max),
+                None => write!(f, "({}){{{},}}", node, min),
+            },
+        }
+    }
+}
+
+impl fmt::Display for PatternAST {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.0 {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Export a predicate set as glob strings, one per predicate.
+///
+/// A prefix term is exported as its text followed by a wildcard,
+/// and is parsed back as the equivalent pattern.
+/// A pattern of a single literal is exported as plain text,
+/// and is parsed back as the equivalent exact term.
+/// Raw predicates have no glob form, and are left out.
+///
+pub fn to_glob_strings(predicate_set: &MatchPredicateSet) -> Vec<String> {
+    predicate_set
+        .iter()
+        .filter_map(|match_predicate| match match_predicate {
+            MatchPredicate::Term(term_text, TermSemantics::Exact) => {
+                Some(PatternASTNode::Literal(term_text.clone()).to_string())
+            }
+            MatchPredicate::Term(term_text, TermSemantics::Prefix) => Some(
+                PatternAST(vec![
+                    PatternASTNode::Literal(term_text.clone()),
+                    PatternASTNode::Wildcard,
+                ])
+                .to_string(),
+            ),
+            MatchPredicate::Pattern(ast) => Some(ast.to_string()),
+            MatchPredicate::Raw(_) => None,
+        })
+        .collect()
+}
+
+///
+/// Parse glob strings (see `to_glob_strings`) into a predicate set.
+///
+pub fn from_glob_strings<S: AsRef<str>>(globs: &[S]) -> Result<MatchPredicateSet, String> {
+    globs.iter().map(|glob| parse_glob(glob.as_ref())).collect()
+}
+
+///
+/// Parse one glob string into a match predicate.
+///
+pub fn parse_glob(glob: &str) -> Result<MatchPredicate, String> {
+    let nodes = parse_nodes(glob, &mut glob.chars(), false)?;
+
+    match nodes.as_slice() {
+        [] => Ok(MatchPredicate::Term(String::new(), TermSemantics::Exact)),
+        [PatternASTNode::Literal(text)] => {
+            Ok(MatchPredicate::Term(text.clone(), TermSemantics::Exact))
+        }
+        _ => Ok(MatchPredicate::Pattern(PatternAST(nodes))),
+    }
+}
+
+///
+/// Parse nodes until the end of the glob, or the end of the group when `in_group`.
+///
+fn parse_nodes(
+    glob: &str,
+    chars: &mut std::str::Chars,
+    in_group: bool,
+) -> Result<Vec<PatternASTNode>, String> {
+    let mut nodes: Vec<PatternASTNode> = vec![];
+    let mut literal = String::new();
+
+    loop {
+        match chars.next() {
+            None if in_group => {
+                return Err(format!("parse_glob: unterminated group in {:?}", glob));
+            }
+            None => break,
+            Some(')') if in_group => break,
+            Some('\\') => match chars.next() {
+                Some(escaped) => literal.push(escaped),
+                None => return Err(format!("parse_glob: dangling escape in {:?}", glob)),
+            },
+            Some('*') => {
+                flush_literal(&mut literal, &mut nodes);
+                nodes.push(PatternASTNode::Wildcard);
+            }
+            Some('[') => {
+                let (min, max) = take_until(chars, ']')
+                    .and_then(|range| parse_num_range(&range))
+                    .ok_or_else(|| format!("parse_glob: invalid range in {:?}", glob))?;
+
+                flush_literal(&mut literal, &mut nodes);
+                nodes.push(PatternASTNode::NumRange { min, max });
+            }
+            Some('(') => {
+                let mut group_nodes = parse_nodes(glob, chars, true)?;
+                if group_nodes.len() != 1 {
+                    return Err(format!(
+                        "parse_glob: a group must contain exactly one node in {:?}",
+                        glob
+                    ));
+                }
+                let (min, max) = match chars.next() {
+                    Some('{') => take_until(chars, '}').and_then(|bounds| parse_bounds(&bounds)),
+                    _ => None,
+                }
+                .ok_or_else(|| format!("parse_glob: invalid repetition in {:?}", glob))?;
+
+                flush_literal(&mut literal, &mut nodes);
+                nodes.push(PatternASTNode::Repeat {
+                    node: Box::new(group_nodes.remove(0)),
+                    min,
+                    max,
+                });
+            }
+            Some(char @ ')') | Some(char @ '{') | Some(char @ '}') => {
+                return Err(format!("parse_glob: unescaped {:?} in {:?}", char, glob));
+            }
+            Some(char) => literal.push(char),
+        }
+    }
+
+    flush_literal(&mut literal, &mut nodes);
+
+    Ok(nodes)
+}
+
+///
+/// The text up to (and consuming) the `end` char, if any.
+///
+fn take_until(chars: &mut std::str::Chars, end: char) -> Option<String> {
+    let mut text = String::new();
+
+    for char in chars {
+        if char == end {
+            return Some(text);
+        }
+        text.push(char);
+    }
+
+    None
+}
+
+fn flush_literal(literal: &mut String, nodes: &mut Vec<PatternASTNode>) {
+    if !literal.is_empty() {
+        nodes.push(PatternASTNode::Literal(std::mem::take(literal)));
+    }
+}
+
+///
+/// Parse `min-max`, where both `min` and `max` may be negative.
+///
+fn parse_num_range(range: &str) -> Option<(i64, i64)> {
+    let separator = range.get(1..)?.find('-')? + 1;
+
+    Some((
+        range[..separator].parse().ok()?,
+        range[separator + 1..].parse().ok()?,
+    ))
+}
+
+///
+/// Parse `min,max` or `min,` of a repetition.
+///
+fn parse_bounds(bounds: &str) -> Option<(usize, Option<usize>)> {
+    let separator = bounds.find(',')?;
+    let max = &bounds[separator + 1..];
+
+    Some((
+        bounds[..separator].parse().ok()?,
+        if max.is_empty() {
+            None
+        } else {
+            Some(max.parse().ok()?)
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display_escapes_glob_syntax_in_literals() {
+        let ast = PatternAST(vec![
+            PatternASTNode::Literal("a*b[c\\".to_string()),
+            PatternASTNode::Wildcard,
+            PatternASTNode::NumRange { min: -9, max: -1 },
+        ]);
+
+        assert_eq!(ast.to_string(), "a\\*b\\[c\\\\*[-9--1]");
+    }
+
+    #[test]
+    fn glob_strings_round_trip() {
+        let mut predicate_set = test_util::create_predicate_set(&[
+            &["foo"],
+            &["*", "bar"],
+            &["ba", "*", "z"],
+            &["v", "[10-20]", "*"],
+            &["[-5-5]"],
+        ]);
+        predicate_set.insert(MatchPredicate::Term(
+            "a*[b]\\".to_string(),
+            TermSemantics::Exact,
+        ));
+        predicate_set.insert(MatchPredicate::Pattern(PatternAST(vec![
+            PatternASTNode::Wildcard,
+            PatternASTNode::Literal("[x](y){z}".to_string()),
+        ])));
+        predicate_set.insert(MatchPredicate::Pattern(PatternAST(vec![
+            PatternASTNode::Repeat {
+                node: Box::new(PatternASTNode::Literal("a(b)".to_string())),
+                min: 0,
+                max: None,
+            },
+            PatternASTNode::Wildcard,
+        ])));
+
+        let globs = to_glob_strings(&predicate_set);
+
+        assert_eq!(from_glob_strings(&globs).unwrap(), predicate_set);
+
+        let single_literal: MatchPredicateSet = vec![MatchPredicate::Pattern(PatternAST(vec![
+            PatternASTNode::Literal("foo".to_string()),
+        ]))]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            from_glob_strings(&to_glob_strings(&single_literal)).unwrap(),
+            vec![MatchPredicate::Term(
+                "foo".to_string(),
+                TermSemantics::Exact
+            )]
+            .into_iter()
+            .collect::<MatchPredicateSet>()
+        );
+    }
+
+    #[test]
+    fn parse_glob_rejects_malformed_globs() {
+        assert!(parse_glob("foo\\").is_err());
+        assert!(parse_glob("[10-20").is_err());
+        assert!(parse_glob("[foo]").is_err());
+        assert!(parse_glob("(a){2").is_err());
+        assert!(parse_glob("(a)").is_err());
+        assert!(parse_glob("(a*){2,3}").is_err());
+        assert!(parse_glob("a{2,3}").is_err());
+    }
+
+    #[test]
+    fn parse_glob_parses_repeats() {
+        assert_eq!(
+            parse_glob("x(ab){2,4}*").unwrap(),
+            MatchPredicate::Pattern(PatternAST(vec![
+                PatternASTNode::Literal("x".to_string()),
+                PatternASTNode::Repeat {
+                    node: Box::new(PatternASTNode::Literal("ab".to_string())),
+                    min: 2,
+                    max: Some(4),
+                },
+                PatternASTNode::Wildcard,
+            ]))
+        );
+        assert_eq!(
+            parse_glob("(([0-9]){3,}){1,2}").unwrap(),
+            MatchPredicate::Pattern(PatternAST(vec![PatternASTNode::Repeat {
+                node: Box::new(PatternASTNode::Repeat {
+                    node: Box::new(PatternASTNode::NumRange { min: 0, max: 9 }),
+                    min: 3,
+                    max: None,
+                }),
+                min: 1,
+                max: Some(2),
+            }]))
+        );
+    }
+}
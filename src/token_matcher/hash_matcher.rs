@@ -1,4 +1,6 @@
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
+use std::hash::BuildHasher;
 
 use super::*;
 
@@ -8,25 +10,97 @@ use super::*;
 /// This matcher should be used if there are no wildcard queries to process.
 ///
 pub struct HashMatcher {
-    term_doc_freq_reciprocals_map: HashMap<String, DocFreqReciprocal>,
+    term_doc_freq_reciprocals_map: hashbrown::HashMap<String, DocFreqReciprocal, RandomState>,
+    metrics: Metrics,
 }
 
 impl HashMatcher {
     pub fn new(term_doc_freq_reciprocals_map: &HashMap<String, DocFreqReciprocal>) -> Self {
         Self {
-            term_doc_freq_reciprocals_map: term_doc_freq_reciprocals_map.clone(),
+            term_doc_freq_reciprocals_map: term_doc_freq_reciprocals_map
+                .iter()
+                .map(|(term_text, dfr)| (term_text.clone(), dfr.clone()))
+                .collect(),
+            metrics: Metrics::default(),
         }
     }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    ///
+    /// Hash a token the way `lookup_by_hash` expects, e.g. during tokenization.
+    ///
+    /// Every matcher has its own randomly keyed hasher, protecting against
+    /// tokens chosen to collide, so a hash is only valid for the matcher that computed it.
+    ///
+    pub fn token_hash(&self, token_text: &str) -> u64 {
+        self.term_doc_freq_reciprocals_map
+            .hasher()
+            .hash_one(token_text)
+    }
+
+    ///
+    /// Look up a token whose hash was precomputed, skipping the rehash.
+    ///
+    /// `hash` must equal `self.token_hash(token_text)`, computed by this very matcher.
+    /// With a hash computed any other way, a lookup of a known term may silently fail.
+    /// The token text is still compared to rule out hash collisions.
+    ///
+    pub fn lookup_by_hash(
+        &mut self,
+        hash: u64,
+        token_text: &str,
+        _get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        self.metrics.lookups += 1;
+
+        let (_, doc_freq_reciprocal) = self
+            .term_doc_freq_reciprocals_map
+            .raw_entry()
+            .from_key_hashed_nocheck(hash, token_text)?;
+        let doc_freq_reciprocal = doc_freq_reciprocal.clone();
+
+        // Every match is served from the precomputed map:
+        self.metrics.matches += 1;
+        self.metrics.cache_hits += 1;
+
+        Some(doc_freq_reciprocal)
+    }
 }
 
 impl LookupDocFreqReciprocal for HashMatcher {
     fn lookup_doc_freq_reciprocal(
         &mut self,
         token_text: &str,
-        _get_doc_freq: &impl GetDocFreq,
+        get_doc_freq: &impl GetDocFreq,
     ) -> Option<DocFreqReciprocal> {
-        self.term_doc_freq_reciprocals_map
-            .get(token_text)
-            .map(|dfr| dfr.clone())
+        let hash = self.token_hash(token_text);
+        self.lookup_by_hash(hash, token_text, get_doc_freq)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hash_and_string_lookups_agree() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["bar"], &["baz"]]);
+        let mut matcher = HashMatcher::new(
+            &test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set),
+        );
+
+        for token in &["foo", "bar", "baz", "qux", ""] {
+            let by_string = matcher.lookup_doc_freq_reciprocal(token, &test_util::AnyTermDb);
+            let hash = matcher.token_hash(token);
+            let by_hash = matcher.lookup_by_hash(hash, token, &test_util::AnyTermDb);
+
+            assert_eq!(by_string, by_hash, "{}", token);
+        }
+
+        assert_eq!(matcher.metrics().lookups, 10);
+        assert_eq!(matcher.metrics().matches, 6);
     }
 }
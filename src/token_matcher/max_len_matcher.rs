@@ -0,0 +1,66 @@
+use super::*;
+
+///
+/// Matcher wrapper rejecting tokens longer than a maximum length,
+/// without passing them on to the inner matcher.
+///
+/// This caps the work done per token whatever the backend,
+/// e.g. for adversarial giant tokens.
+/// The length is measured in bytes, so checking it is free.
+///
+pub struct MaxLenMatcher<M> {
+    inner: M,
+    max_len: usize,
+}
+
+impl<M: LookupDocFreqReciprocal> MaxLenMatcher<M> {
+    pub fn new(inner: M, max_len: usize) -> Self {
+        Self { inner, max_len }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+impl<M: LookupDocFreqReciprocal> LookupDocFreqReciprocal for MaxLenMatcher<M> {
+    fn lookup_doc_freq_reciprocal(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        if token_text.len() > self.max_len {
+            return None;
+        }
+
+        self.inner
+            .lookup_doc_freq_reciprocal(token_text, get_doc_freq)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn over_length_tokens_are_rejected_without_calling_the_inner_matcher() {
+        let mut matcher =
+            MaxLenMatcher::new(regex_matcher::test::test_regex_matcher(&[&["foo", "*"]]), 6);
+
+        assert!(test_util::matches(&mut matcher, "foo"));
+        assert!(test_util::matches(&mut matcher, "foobar"));
+        assert_eq!(matcher.inner().metrics().lookups, 2);
+
+        assert!(!test_util::matches(&mut matcher, "foobarbaz"));
+        assert!(!test_util::matches(&mut matcher, &"foo".repeat(100_000)));
+        assert_eq!(matcher.inner().metrics().lookups, 2);
+    }
+}
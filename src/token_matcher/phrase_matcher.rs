@@ -0,0 +1,182 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+///
+/// A sequence of tokens that must appear next to each other, in order.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct PhrasePredicate(pub Vec<String>);
+
+///
+/// Matcher for phrases in a document that has been flattened to a single string
+/// by joining its tokens with a separator.
+///
+/// This provides phrase matching without a positional index, reusing the regex backend:
+/// a phrase "foo bar" is compiled to the escaped tokens joined by the escaped separator.
+///
+/// The separator must never occur inside a token. When it does, the joined string
+/// becomes ambiguous: with separator " ", the single token "foo bar" is indistinguishable
+/// from the two tokens "foo" and "bar", so phrases may match across token boundaries
+/// that do not exist in the original token stream. Pick a separator that the tokenizer
+/// can never produce, e.g. a control character like '\u{1f}'.
+///
+pub struct PhraseMatcher {
+    regex: regex::Regex,
+    capture_locations_buf: regex::CaptureLocations,
+}
+
+impl PhraseMatcher {
+    pub fn new(regex: regex::Regex) -> Self {
+        let capture_locations_buf = regex.capture_locations();
+
+        Self {
+            regex,
+            capture_locations_buf,
+        }
+    }
+
+    ///
+    /// Byte ranges of all non-overlapping phrase occurrences in the joined tokens.
+    ///
+    pub fn find_phrases(&mut self, joined_tokens: &str) -> Vec<Range<usize>> {
+        let mut ranges = vec![];
+        let mut start = 0;
+
+        // The trailing separator of a match is not consumed,
+        // so that it can act as the leading separator of the next one:
+        while self
+            .regex
+            .captures_read_at(&mut self.capture_locations_buf, joined_tokens, start)
+            .is_some()
+        {
+            match self.capture_locations_buf.get(1) {
+                Some((phrase_start, phrase_end)) if phrase_start == phrase_end => {
+                    // Never produced by `compile_phrase_regex`, but must not loop forever:
+                    match joined_tokens[phrase_end..].chars().next() {
+                        Some(next_char) => start = phrase_end + next_char.len_utf8(),
+                        None => break,
+                    }
+                }
+                Some((phrase_start, phrase_end)) => {
+                    ranges.push(phrase_start..phrase_end);
+                    start = phrase_end;
+                }
+                None => break,
+            }
+        }
+
+        ranges
+    }
+
+    pub fn is_match(&self, joined_tokens: &str) -> bool {
+        self.regex.is_match(joined_tokens)
+    }
+}
+
+///
+/// Compile phrases for `PhraseMatcher`.
+///
+/// Phrases that are empty or contain an empty token are skipped, as they would match
+/// between two separators. Without any phrases left, the regex never matches.
+///
+pub fn compile_phrase_regex(
+    phrases: &BTreeSet<PhrasePredicate>,
+    separator: &str,
+) -> Result<regex::Regex, String> {
+    let escaped_separator = regex_syntax::escape(separator);
+
+    let phrase_exprs: Vec<String> = phrases
+        .iter()
+        .filter(|phrase| !phrase.0.is_empty() && phrase.0.iter().all(|token| !token.is_empty()))
+        .map(|phrase| {
+            phrase
+                .0
+                .iter()
+                .map(|token| regex_syntax::escape(token))
+                .collect::<Vec<_>>()
+                .join(&escaped_separator)
+        })
+        .collect();
+
+    if phrase_exprs.is_empty() {
+        // Nothing can precede the start of the text, so this never matches:
+        return regex::Regex::new("x^")
+            .map_err(|error| format!("compile_phrase_regex failed. {:?}", error));
+    }
+
+    let regex_pattern = format!(
+        "(?:^|{sep})({phrases})(?:{sep}|$)",
+        sep = escaped_separator,
+        phrases = phrase_exprs.join("|")
+    );
+
+    regex::Regex::new(&regex_pattern)
+        .map_err(|error| format!("compile_phrase_regex failed. {:?}", error))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_phrase_matcher(phrases: &[&[&str]], separator: &str) -> PhraseMatcher {
+        let phrases = phrases
+            .iter()
+            .map(|tokens| PhrasePredicate(tokens.iter().map(|token| token.to_string()).collect()))
+            .collect();
+
+        PhraseMatcher::new(compile_phrase_regex(&phrases, separator).unwrap())
+    }
+
+    #[test]
+    fn matches_two_word_phrase_in_joined_document() {
+        let mut matcher = test_phrase_matcher(&[&["new", "york"]], "|");
+
+        assert_eq!(
+            matcher.find_phrases("i|love|new|york|and|new|york|loves|me"),
+            vec![7..15, 20..28]
+        );
+        assert!(matcher.is_match("new|york"));
+        assert!(!matcher.is_match("new|yorker"));
+        assert!(!matcher.is_match("york|new"));
+    }
+
+    #[test]
+    fn finds_adjacent_phrase_occurrences() {
+        let mut matcher = test_phrase_matcher(&[&["a", "b"]], ".");
+
+        assert_eq!(matcher.find_phrases("a.b.a.b"), vec![0..3, 4..7]);
+    }
+
+    #[test]
+    fn empty_phrase_set_matches_nothing() {
+        let mut matcher = test_phrase_matcher(&[], " ");
+
+        assert_eq!(matcher.find_phrases(""), vec![]);
+        assert_eq!(matcher.find_phrases("a  b"), vec![]);
+        assert!(!matcher.is_match(""));
+    }
+
+    #[test]
+    fn empty_document_has_no_phrases() {
+        let mut matcher = test_phrase_matcher(&[&["a", "b"]], " ");
+
+        assert_eq!(matcher.find_phrases(""), vec![]);
+        assert!(!matcher.is_match(""));
+    }
+
+    #[test]
+    fn doubled_separator_does_not_match_phrases_with_empty_tokens() {
+        let mut matcher = test_phrase_matcher(&[&["b"], &["a", ""], &[""]], " ");
+
+        assert_eq!(matcher.find_phrases("a  b"), vec![3..4]);
+    }
+
+    #[test]
+    fn separator_inside_a_token_is_ambiguous() {
+        let mut matcher = test_phrase_matcher(&[&["foo", "bar"]], " ");
+
+        // The single token "foo bar" joined with " " looks just like a phrase:
+        let joined_tokens = ["x", "foo bar", "y"].join(" ");
+        assert_eq!(matcher.find_phrases(&joined_tokens), vec![2..9]);
+    }
+}
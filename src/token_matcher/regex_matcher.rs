@@ -1,20 +1,42 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 
+use fixedbitset::FixedBitSet;
+
 use crate::PatternASTNode;
 
+use super::regex_util::{
+    char_class_regex_expr, node_to_regex_expr, split_wide_terms, CompileOptions, GroupedPatterns,
+    PatternGroup, WildcardQuantifier,
+};
 use super::*;
 
 pub struct RegexMatcher {
     regex: regex::Regex,
     capture_locations_buf: regex::CaptureLocations,
+    // The regex anchored at both ends, and the regex anchored at the start only
+    // (matching some prefix of the token), compiled on demand by `is_full_match`
+    full_match_regexes: Option<(regex::Regex, regex::Regex)>,
+    // The size limit of the regexes compiled by `is_full_match`
+    size_limit: Option<usize>,
 
     term_count: usize,
 
     // A vector of term doc freqs, indexed by the Regex' intial term groups
     term_doc_freq_reciprocals: Vec<Option<DocFreqReciprocal>>,
+    // The doc freqs of single character terms compiled into a character class, having no group
+    char_term_doc_freq_reciprocals: HashMap<String, DocFreqReciprocal>,
     pattern_doc_freq_cache: HashMap<String, Option<DocFreqReciprocal>>,
 
-    term_buf: crate::Term,
+    case_insensitive: bool,
+    // The lowercased copy of the most recent token, when matching case-insensitively
+    normalized_buf: String,
+    // For every byte of normalized_buf: the span of the original char it was lowercased from
+    original_char_spans: Vec<(usize, usize)>,
+
+    default_reciprocal: Option<DocFreqReciprocal>,
+
+    metrics: Metrics,
 }
 
 impl RegexMatcher {
@@ -23,17 +45,40 @@ impl RegexMatcher {
         predicate_set: &MatchPredicateSet,
         term_doc_freq_reciprocals_map: &HashMap<String, DocFreqReciprocal>,
     ) -> Self {
-        let mut term_doc_freq_reciprocals: Vec<Option<DocFreqReciprocal>> = vec![];
-        let mut term_count = 0;
-
-        for match_predicate in predicate_set {
-            if let MatchPredicate::Term(term_text) = match_predicate {
-                term_doc_freq_reciprocals.push(
-                    term_doc_freq_reciprocals_map
-                        .get(term_text)
-                        .map(|dfr| dfr.clone()),
-                );
-                term_count += 1;
+        Self::from_groups(
+            regex,
+            &GroupedPatterns::group(predicate_set),
+            term_doc_freq_reciprocals_map,
+        )
+    }
+
+    ///
+    /// Create a matcher for a regex compiled with `compile_regex_from_groups`.
+    ///
+    /// Term groups missing from the doc freq map (e.g. single-literal patterns)
+    /// have their doc freq looked up like any other pattern match.
+    ///
+    /// Single character terms compiled into a character class have no capture group,
+    /// so their doc freqs are looked up by the matched token instead.
+    ///
+    pub fn from_groups(
+        regex: regex::Regex,
+        groups: &GroupedPatterns,
+        term_doc_freq_reciprocals_map: &HashMap<String, DocFreqReciprocal>,
+    ) -> Self {
+        let (term_alternatives, term_chars) = split_wide_terms(&groups.terms);
+
+        let term_doc_freq_reciprocals: Vec<Option<DocFreqReciprocal>> = term_alternatives
+            .iter()
+            .map(|term_text| term_doc_freq_reciprocals_map.get(*term_text).cloned())
+            .collect();
+        let term_count = term_doc_freq_reciprocals.len();
+
+        let mut char_term_doc_freq_reciprocals = HashMap::new();
+        for char in term_chars {
+            let term_text = char.to_string();
+            if let Some(doc_freq_reciprocal) = term_doc_freq_reciprocals_map.get(&term_text) {
+                char_term_doc_freq_reciprocals.insert(term_text, doc_freq_reciprocal.clone());
             }
         }
 
@@ -42,21 +87,244 @@ impl RegexMatcher {
         Self {
             regex,
             capture_locations_buf,
+            full_match_regexes: None,
+            size_limit: None,
             term_count,
             term_doc_freq_reciprocals,
+            char_term_doc_freq_reciprocals,
             pattern_doc_freq_cache: HashMap::new(),
-            term_buf: crate::Term::default(),
+            case_insensitive: false,
+            normalized_buf: String::new(),
+            original_char_spans: vec![],
+            default_reciprocal: None,
+            metrics: Metrics::default(),
         }
     }
 
-    fn text_term(&mut self, token_text: &str) -> &crate::Term {
-        self.term_buf.set_text(token_text);
-        &self.term_buf
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
     }
-}
 
-impl LookupDocFreqReciprocal for RegexMatcher {
-    fn lookup_doc_freq_reciprocal(
+    ///
+    /// Set the reciprocal of matched tokens having an unknown doc freq
+    /// (see `GetDocFreq::try_get_doc_freq`), so that they contribute to the score.
+    /// With `None` (the default) such tokens get no reciprocal.
+    /// Tokens with a doc freq of 0 never get a reciprocal.
+    ///
+    /// Term predicates missing from the doc freq map have their doc freq looked up
+    /// (see `from_groups`), so they get the default reciprocal when that is unknown.
+    /// The other backends don't look them up, and always give them the default reciprocal.
+    ///
+    /// The reciprocal is cached along with the doc freqs,
+    /// so it must be set before looking up any tokens.
+    ///
+    pub fn set_default_reciprocal(&mut self, default_reciprocal: Option<DocFreqReciprocal>) {
+        self.default_reciprocal = default_reciprocal;
+    }
+
+    ///
+    /// Forget the cached doc freq of a token, so that the next lookup of it calls `GetDocFreq` again,
+    /// e.g. after its doc freq changed in a live index.
+    ///
+    /// Term predicates are scored by the doc freq map given when creating the matcher,
+    /// and are not affected: their reciprocals stay stale until the matcher is recreated
+    /// with an updated map (see `transfer_cache` for keeping the other cached doc freqs).
+    ///
+    pub fn invalidate_term(&mut self, term_text: &str) {
+        self.pattern_doc_freq_cache.remove(term_text);
+    }
+
+    ///
+    /// Inherit the doc freqs cached by another regex matcher, e.g. the one being replaced
+    /// after a predicate change, so that tokens it has seen don't call `GetDocFreq` again.
+    /// Doc freqs already cached by this matcher are kept.
+    ///
+    pub fn transfer_cache(&mut self, from: &Self) {
+        for (token_text, doc_freq_reciprocal) in &from.pattern_doc_freq_cache {
+            self.pattern_doc_freq_cache
+                .entry(token_text.clone())
+                .or_insert_with(|| doc_freq_reciprocal.clone());
+        }
+    }
+
+    ///
+    /// Whether each token matches, one bit per token,
+    /// for callers not interested in doc freqs: this never calls `GetDocFreq`.
+    ///
+    pub fn match_mask(&mut self, tokens: &[&str]) -> FixedBitSet {
+        let mut mask = FixedBitSet::with_capacity(tokens.len());
+
+        for (index, token_text) in tokens.iter().enumerate() {
+            self.metrics.lookups += 1;
+            if self.regex.is_match(&self.matched_text(token_text)) {
+                self.metrics.matches += 1;
+                mask.insert(index);
+            }
+        }
+
+        mask
+    }
+
+    ///
+    /// Set the size limit of the regexes compiled by `is_full_match` from the regex of this matcher,
+    /// which should be the `RegexConfig::size_limit` the regex was compiled with.
+    /// With `None` (the default), the default limit of `regex::RegexBuilder` applies.
+    ///
+    pub fn set_size_limit(&mut self, size_limit: Option<usize>) {
+        self.size_limit = size_limit;
+        self.full_match_regexes = None;
+    }
+
+    ///
+    /// Match tokens case-insensitively, by matching a lowercased copy of each token.
+    ///
+    /// The predicates (and term doc freq map) must be lowercase,
+    /// and doc freqs of pattern matches are looked up for the lowercased token.
+    ///
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.case_insensitive = case_insensitive;
+    }
+
+    ///
+    /// Look up a token, also returning its normalized form, i.e. the text actually matched:
+    /// lowercased when matching case-insensitively, and borrowed when normalizing changed nothing.
+    ///
+    pub fn lookup_normalized<'t>(
+        &mut self,
+        token_text: &'t str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> (Option<DocFreqReciprocal>, Cow<'t, str>) {
+        let doc_freq_reciprocal = self.lookup_doc_freq_reciprocal(token_text, get_doc_freq);
+
+        let normalized = if self.case_insensitive && self.normalized_buf != token_text {
+            Cow::Owned(self.normalized_buf.clone())
+        } else {
+            Cow::Borrowed(token_text)
+        };
+
+        (doc_freq_reciprocal, normalized)
+    }
+
+    ///
+    /// The raw capture locations of the most recent `lookup_*` call,
+    /// for callers doing their own highlighting without running the regex again.
+    ///
+    /// Group 0 is the span of the whole match, followed by one group per term.
+    /// The locations are only meaningful if the most recent lookup matched.
+    ///
+    /// When matching case-insensitively, the locations are offsets into the lowercased copy
+    /// of the token, see `last_match_span` for offsets into the original token.
+    ///
+    pub fn last_capture_locations(&self) -> &regex::CaptureLocations {
+        &self.capture_locations_buf
+    }
+
+    ///
+    /// The span of a capture group of the most recent `lookup_*` call,
+    /// as byte offsets into the original token, also when matching case-insensitively.
+    ///
+    /// Group 0 is the span of the whole match, see `last_capture_locations`.
+    ///
+    pub fn last_match_span(&self, group: usize) -> Option<(usize, usize)> {
+        let (start, end) = self.capture_locations_buf.get(group)?;
+
+        if !self.case_insensitive {
+            return Some((start, end));
+        }
+
+        let original_start = match self.original_char_spans.get(start) {
+            Some((char_start, _)) => *char_start,
+            // The match starts at the end of the token
+            None => self
+                .original_char_spans
+                .last()
+                .map_or(0, |(_, char_end)| *char_end),
+        };
+        let original_end = match end {
+            0 => 0,
+            _ => self.original_char_spans[end - 1].1,
+        };
+
+        Some((original_start, original_end))
+    }
+
+    ///
+    /// Whether a predicate matches the token as a whole (`Some(true)`),
+    /// only a prefix of it (`Some(false)`), or neither (`None`),
+    /// the same as `AutomatonMatcher::is_full_match`.
+    ///
+    /// This differs from lookups, which search the token: only some branches of the regex
+    /// are anchored at both ends, e.g. `*qu*` is found in "😀qu" even though the wildcard
+    /// does not match '😀'. Here that is `None`, while "qu😀" is `Some(false)`.
+    ///
+    /// A raw predicate asserting the end of the token other than at its end (e.g. `a$b?`)
+    /// is only taken to match a prefix ending where the token ends.
+    ///
+    pub fn is_full_match(&mut self, token_text: &str) -> Option<bool> {
+        let matched_text = self.matched_text(token_text);
+
+        let (regex, size_limit) = (&self.regex, self.size_limit);
+        let (full_match_regex, prefix_match_regex) =
+            self.full_match_regexes.get_or_insert_with(|| {
+                let build = |pattern: &str| {
+                    let mut builder = regex::RegexBuilder::new(pattern);
+                    if let Some(size_limit) = size_limit {
+                        builder.size_limit(size_limit);
+                    }
+                    builder
+                        .build()
+                        .expect("anchoring a compiled regex keeps it valid and about the same size")
+                };
+
+                let hir = regex_syntax::Parser::new()
+                    .parse(regex.as_str())
+                    .expect("a compiled regex parses");
+
+                (
+                    build(&format!("^(?:{})$", regex.as_str())),
+                    build(&format!("^(?:{})", without_trailing_end_anchors(hir))),
+                )
+            });
+
+        // Each search is a single linear pass over the token:
+        if full_match_regex.is_match(&matched_text) {
+            Some(true)
+        } else if prefix_match_regex.is_match(&matched_text) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// The text to match for a token, lowercased like `normalize` when matching case-insensitively,
+    /// leaving the buffers of the most recent lookup alone.
+    ///
+    fn matched_text<'t>(&self, token_text: &'t str) -> Cow<'t, str> {
+        if self.case_insensitive {
+            Cow::Owned(token_text.chars().flat_map(char::to_lowercase).collect())
+        } else {
+            Cow::Borrowed(token_text)
+        }
+    }
+
+    ///
+    /// Lowercase a token into `normalized`, recording where each byte came from.
+    ///
+    fn normalize(&mut self, token_text: &str, normalized: &mut String) {
+        normalized.clear();
+        self.original_char_spans.clear();
+
+        for (char_start, char) in token_text.char_indices() {
+            let char_span = (char_start, char_start + char.len_utf8());
+            for lowercase_char in char.to_lowercase() {
+                normalized.push(lowercase_char);
+                self.original_char_spans.resize(normalized.len(), char_span);
+            }
+        }
+    }
+
+    fn lookup_text(
         &mut self,
         token_text: &str,
         get_doc_freq: &impl GetDocFreq,
@@ -65,25 +333,39 @@ impl LookupDocFreqReciprocal for RegexMatcher {
             .regex
             .captures_read(&mut self.capture_locations_buf, token_text)?;
 
+        self.metrics.matches += 1;
+
         // Loop through terms and see if we find the doc_freq_reciprocal
         // BUG: is this really faster than using a HashMap?
         for term_index in 0..self.term_count {
             if let Some(_) = self.capture_locations_buf.get(term_index + 1) {
-                return self
-                    .term_doc_freq_reciprocals
-                    .get(term_index)
-                    .and_then(|dfr| dfr.clone());
+                if let Some(doc_freq_reciprocal) = &self.term_doc_freq_reciprocals[term_index] {
+                    self.metrics.cache_hits += 1;
+                    return Some(doc_freq_reciprocal.clone());
+                }
+                break;
             }
         }
 
+        if let Some(doc_freq_reciprocal) = self.char_term_doc_freq_reciprocals.get(token_text) {
+            self.metrics.cache_hits += 1;
+            return Some(doc_freq_reciprocal.clone());
+        }
+
         let opt_pattern_doc_freq = self.pattern_doc_freq_cache.get(token_text);
 
         if let Some(pattern_doc_freq) = opt_pattern_doc_freq {
+            self.metrics.cache_hits += 1;
             return pattern_doc_freq.clone();
         }
 
-        let term = self.text_term(token_text);
-        let doc_freq_reciprocal = DocFreqReciprocal::from_doc_freq(get_doc_freq.get_doc_freq(term));
+        self.metrics.cache_misses += 1;
+        self.metrics.get_doc_freq_calls += 1;
+
+        let doc_freq_reciprocal = DocFreqReciprocal::from_try_doc_freq(
+            get_doc_freq.try_get_doc_freq(crate::TermRef::from(token_text)),
+            &self.default_reciprocal,
+        );
 
         self.pattern_doc_freq_cache
             .insert(token_text.to_string(), doc_freq_reciprocal.clone());
@@ -92,29 +374,245 @@ impl LookupDocFreqReciprocal for RegexMatcher {
     }
 }
 
+impl LookupDocFreqReciprocal for RegexMatcher {
+    fn lookup_doc_freq_reciprocal(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        self.metrics.lookups += 1;
+
+        if !self.case_insensitive {
+            return self.lookup_text(token_text, get_doc_freq);
+        }
+
+        let mut normalized = std::mem::take(&mut self.normalized_buf);
+        self.normalize(token_text, &mut normalized);
+        let doc_freq_reciprocal = self.lookup_text(&normalized, get_doc_freq);
+        self.normalized_buf = normalized;
+
+        doc_freq_reciprocal
+    }
+}
+
 enum CompileStrategy {
     VeryFlat,
     Flat,
     Grouped,
 }
 
+///
+/// Tuning of the regex engine used by `compile_regex_with_config`.
+///
+/// The default configuration is the same as `regex::Regex::new`.
+///
+#[derive(Clone, Debug)]
+pub struct RegexConfig {
+    /// Approximate size limit of the compiled regex, see `regex::RegexBuilder::size_limit`.
+    /// Pass it to `RegexMatcher::set_size_limit` as well, for `RegexMatcher::is_full_match`
+    pub size_limit: Option<usize>,
+    /// Approximate size limit of the lazy DFA cache, see `regex::RegexBuilder::dfa_size_limit`
+    pub dfa_size_limit: Option<usize>,
+    /// Unicode-aware matching. When disabled, wildcards only match ASCII characters,
+    /// and the regex is compiled for an ASCII alphabet if every literal is ASCII too
+    /// (see `uses_ascii_alphabet`), falling back to Unicode otherwise.
+    pub unicode: bool,
+    /// How wildcards quantify, see `WildcardQuantifier`.
+    /// This only changes match spans, not which tokens match, nor `RegexMatcher::is_full_match`
+    pub wildcard_quantifier: WildcardQuantifier,
+}
+
+impl Default for RegexConfig {
+    fn default() -> Self {
+        Self {
+            size_limit: None,
+            dfa_size_limit: None,
+            unicode: true,
+            wildcard_quantifier: WildcardQuantifier::Greedy,
+        }
+    }
+}
+
+impl RegexConfig {
+    ///
+    /// Whether the groups are compiled with `regex::RegexBuilder::unicode(false)`,
+    /// leaving Unicode support out of the program of the (still `&str` based) `regex::Regex`.
+    ///
+    /// This is not detected automatically: the configured wildcard range is only ASCII
+    /// when the caller opts in by disabling `unicode` (or with `CompileOptions::ascii_wildcards`),
+    /// and even then any non-ASCII literal or any raw expression falls back to Unicode.
+    ///
+    pub fn uses_ascii_alphabet(&self, groups: &GroupedPatterns) -> bool {
+        !self.unicode && groups.is_ascii()
+    }
+
+    fn wildcard_expr(&self) -> Cow<'static, str> {
+        self.wildcard_quantifier.wildcard_expr(self.unicode)
+    }
+}
+
 pub fn compile_regex(predicate_set: &MatchPredicateSet) -> Result<regex::Regex, String> {
-    let regex_pattern = generate_regex_pattern(predicate_set, r#"[\x{0000}-\x{024f}]*"#);
+    compile_regex_with_config(predicate_set, &RegexConfig::default())
+}
+
+pub fn compile_regex_with_config(
+    predicate_set: &MatchPredicateSet,
+    config: &RegexConfig,
+) -> Result<regex::Regex, String> {
+    compile_regex_from_groups(&GroupedPatterns::group(predicate_set), config)
+}
+
+///
+/// Compile a regex, first checking the predicate set against the limits of the options.
+///
+pub fn compile_regex_with_options(
+    predicate_set: &MatchPredicateSet,
+    options: &CompileOptions,
+) -> Result<regex::Regex, String> {
+    options
+        .check(predicate_set)
+        .map_err(|error| format!("compile_regex: {}", error))?;
+
+    compile_regex_with_config(
+        predicate_set,
+        &RegexConfig {
+            unicode: !options.ascii_wildcards,
+            ..RegexConfig::default()
+        },
+    )
+}
+
+///
+/// Compile predicates that are already sorted and deduplicated, see `GroupedPatterns::group_sorted`.
+///
+/// Use `RegexMatcher::from_groups` to create a matcher for the result.
+///
+pub fn compile_regex_sorted(predicates: &[MatchPredicate]) -> Result<regex::Regex, String> {
+    compile_regex_from_groups(
+        &GroupedPatterns::group_sorted(predicates),
+        &RegexConfig::default(),
+    )
+}
+
+///
+/// Compile an already grouped predicate set,
+/// allowing the caller to preprocess the groups before the regex is generated.
+///
+/// Use `RegexMatcher::from_groups` to create a matcher for the result.
+///
+pub fn compile_regex_from_groups(
+    groups: &GroupedPatterns,
+    config: &RegexConfig,
+) -> Result<regex::Regex, String> {
+    let regex_pattern = generate_regex_pattern_from_groups(groups, &config.wildcard_expr());
 
     println!("re pattern: {}", regex_pattern);
 
-    regex::Regex::new(&regex_pattern).map_err(|error| format!("compile_regex failed. {:?}", error))
+    let mut builder = regex::RegexBuilder::new(&regex_pattern);
+    builder.unicode(!config.uses_ascii_alphabet(groups));
+    if let Some(size_limit) = config.size_limit {
+        builder.size_limit(size_limit);
+    }
+    if let Some(dfa_size_limit) = config.dfa_size_limit {
+        builder.dfa_size_limit(dfa_size_limit);
+    }
+
+    builder
+        .build()
+        .map_err(|error| format!("compile_regex failed. {:?}", error))
+}
+
+///
+/// One separately compiled matcher per pattern group (see `GroupedPatterns`),
+/// or `None` for groups without predicates.
+///
+/// The groups can be replaced or combined flexibly,
+/// e.g. by running the `terms` group as a `HashMatcher` instead.
+/// Looking up a token tries every group in turn,
+/// matching the same tokens as a single matcher for the whole predicate set.
+///
+pub struct GroupMatchers {
+    pub terms: Option<RegexMatcher>,
+    pub terms_wc: Option<RegexMatcher>,
+    pub terms_internal_wc: Option<RegexMatcher>,
+    pub wc_terms: Option<RegexMatcher>,
+    pub wc_terms_wc: Option<RegexMatcher>,
+    pub raw: Option<RegexMatcher>,
+}
+
+impl LookupDocFreqReciprocal for GroupMatchers {
+    fn lookup_doc_freq_reciprocal(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        let mut group_matchers = [
+            &mut self.terms,
+            &mut self.terms_wc,
+            &mut self.terms_internal_wc,
+            &mut self.wc_terms,
+            &mut self.wc_terms_wc,
+            &mut self.raw,
+        ];
+
+        group_matchers
+            .iter_mut()
+            .filter_map(|matcher| matcher.as_mut())
+            .find_map(|matcher| matcher.lookup_doc_freq_reciprocal(token_text, get_doc_freq))
+    }
+}
+
+///
+/// Compile every pattern group of a predicate set into its own matcher.
+///
+pub fn compile_by_group(
+    predicate_set: &MatchPredicateSet,
+    term_doc_freq_reciprocals_map: &HashMap<String, DocFreqReciprocal>,
+) -> Result<GroupMatchers, String> {
+    let mut group_predicate_sets: HashMap<PatternGroup, MatchPredicateSet> = HashMap::new();
+    for match_predicate in predicate_set {
+        if let Some(group) = PatternGroup::of(match_predicate) {
+            group_predicate_sets
+                .entry(group)
+                .or_default()
+                .insert(match_predicate.clone());
+        }
+    }
+
+    let compile_group = |group| -> Result<Option<RegexMatcher>, String> {
+        match group_predicate_sets.get(&group) {
+            Some(group_predicate_set) => Ok(Some(RegexMatcher::new(
+                compile_regex(group_predicate_set)?,
+                group_predicate_set,
+                term_doc_freq_reciprocals_map,
+            ))),
+            None => Ok(None),
+        }
+    };
+
+    Ok(GroupMatchers {
+        terms: compile_group(PatternGroup::Terms)?,
+        terms_wc: compile_group(PatternGroup::TermsWc)?,
+        terms_internal_wc: compile_group(PatternGroup::TermsInternalWc)?,
+        wc_terms: compile_group(PatternGroup::WcTerms)?,
+        wc_terms_wc: compile_group(PatternGroup::WcTermsWc)?,
+        raw: compile_group(PatternGroup::Raw)?,
+    })
 }
 
 fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_expr: &str) -> String {
-    let groups = super::regex_util::GroupedPatterns::group(predicate_set);
+    generate_regex_pattern_from_groups(&GroupedPatterns::group(predicate_set), wildcard_expr)
+}
+
+fn generate_regex_pattern_from_groups(groups: &GroupedPatterns, wildcard_expr: &str) -> String {
+    // Wide alternations of terms have their single character terms in a class instead:
+    let (term_alternatives, term_chars) = split_wide_terms(&groups.terms);
 
     let regex_exprs: Vec<Option<String>> = vec![
-        if groups.terms.len() > 0 {
+        if !term_alternatives.is_empty() {
             Some(
-                groups
-                    .terms
-                    .into_iter()
+                term_alternatives
+                    .iter()
                     .map(|term| format!("^({})$", regex_syntax::escape(term)))
                     .collect::<Vec<_>>()
                     .join("|"),
@@ -122,11 +620,16 @@ fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_exp
         } else {
             None
         },
+        if !term_chars.is_empty() {
+            Some(format!("^{}$", char_class_regex_expr(&term_chars)))
+        } else {
+            None
+        },
         if groups.terms_internal_wc.len() > 0 {
             Some(
                 groups
                     .terms_internal_wc
-                    .into_iter()
+                    .iter()
                     .map(|pattern| pattern_to_regex_expr(pattern, wildcard_expr))
                     .filter_map(|opt| opt.map(|expr| format!("^{}$", expr)))
                     .collect::<Vec<_>>()
@@ -135,13 +638,25 @@ fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_exp
         } else {
             None
         },
+        if !groups.prefix_terms.is_empty() {
+            Some(
+                groups
+                    .prefix_terms
+                    .iter()
+                    .map(|term| format!("^{}{}", regex_syntax::escape(term), wildcard_expr))
+                    .collect::<Vec<_>>()
+                    .join("|"),
+            )
+        } else {
+            None
+        },
         if groups.terms_wc.len() > 0 {
             Some(
                 groups
                     .terms_wc
-                    .into_iter()
+                    .iter()
                     .map(|pattern| pattern_to_regex_expr(pattern, wildcard_expr))
-                    .filter_map(|opt| opt.map(|expr| format!("^{}", expr)))
+                    .filter_map(|opt| opt.map(|expr| format!("^{}{}", expr, wildcard_expr)))
                     .collect::<Vec<_>>()
                     .join("|"),
             )
@@ -152,9 +667,9 @@ fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_exp
             Some(
                 groups
                     .wc_terms
-                    .into_iter()
+                    .iter()
                     .map(|pattern| pattern_to_regex_expr(pattern, wildcard_expr))
-                    .filter_map(|opt| opt.map(|expr| format!("{}$", expr)))
+                    .filter_map(|opt| opt.map(|expr| format!("{}{}$", wildcard_expr, expr)))
                     .collect::<Vec<_>>()
                     .join("|"),
             )
@@ -165,15 +680,35 @@ fn generate_regex_pattern(predicate_set: &BTreeSet<MatchPredicate>, wildcard_exp
             Some(
                 groups
                     .wc_terms_wc
-                    .into_iter()
+                    .iter()
                     .map(|pattern| pattern_to_regex_expr(pattern, wildcard_expr))
-                    .filter_map(|opt| opt)
+                    .filter_map(|opt| {
+                        opt.map(|expr| format!("{}{}{}", wildcard_expr, expr, wildcard_expr))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("|"),
+            )
+        } else {
+            None
+        },
+        if !groups.raw.is_empty() {
+            Some(
+                groups
+                    .raw
+                    .iter()
+                    .map(|expr| format!("^(?:{})$", expr))
                     .collect::<Vec<_>>()
                     .join("|"),
             )
         } else {
             None
         },
+        // Last, so that term captures take precedence:
+        if groups.match_all {
+            Some(format!("^{}$", wildcard_expr))
+        } else {
+            None
+        },
     ];
 
     regex_exprs
@@ -196,27 +731,55 @@ fn pattern_to_regex_expr(ast_nodes: &[PatternASTNode], wildcard_expr: &str) -> O
     match ast_nodes.len() {
         0 => None,
         1 => match ast_nodes.first() {
-            Some(PatternASTNode::Literal(text)) => Some(regex_syntax::escape(text)),
             // No "*"!
-            _ => None,
+            Some(PatternASTNode::Wildcard) | None => None,
+            Some(node) => Some(node_to_regex_expr(node, wildcard_expr)),
         },
         _ => Some(format!(
             // "(?:{})",
             "{}",
             ast_nodes
                 .into_iter()
-                .map(|node| {
-                    match node {
-                        PatternASTNode::Literal(text) => regex_syntax::escape(text),
-                        PatternASTNode::Wildcard => wildcard_expr.to_string(),
-                    }
-                })
+                .map(|node| node_to_regex_expr(node, wildcard_expr))
                 .collect::<Vec<_>>()
                 .join("")
         )),
     }
 }
 
+///
+/// Remove the `$` anchors ending the branches of a regex, so that a branch anchored at both ends
+/// matches any token starting with what the branch matches.
+///
+/// Anchors elsewhere are kept, so a raw expression like `a$b?` only matches at the end of the token.
+///
+fn without_trailing_end_anchors(hir: regex_syntax::hir::Hir) -> regex_syntax::hir::Hir {
+    use regex_syntax::hir::{Anchor, Group, Hir, HirKind};
+
+    match hir.into_kind() {
+        HirKind::Anchor(Anchor::EndText) => Hir::empty(),
+        HirKind::Alternation(hirs) => {
+            Hir::alternation(hirs.into_iter().map(without_trailing_end_anchors).collect())
+        }
+        HirKind::Concat(mut hirs) => {
+            if let Some(last) = hirs.pop() {
+                hirs.push(without_trailing_end_anchors(last));
+            }
+            Hir::concat(hirs)
+        }
+        HirKind::Group(group) => Hir::group(Group {
+            kind: group.kind,
+            hir: Box::new(without_trailing_end_anchors(*group.hir)),
+        }),
+        HirKind::Empty => Hir::empty(),
+        HirKind::Literal(literal) => Hir::literal(literal),
+        HirKind::Class(class) => Hir::class(class),
+        HirKind::Anchor(anchor) => Hir::anchor(anchor),
+        HirKind::WordBoundary(word_boundary) => Hir::word_boundary(word_boundary),
+        HirKind::Repetition(repetition) => Hir::repetition(repetition),
+    }
+}
+
 // #[cfg(test)]
 pub mod test {
     use super::test_util;
@@ -237,4 +800,617 @@ pub mod test {
     fn test_generate_regex_pattern(patterns: &[&[&str]]) -> String {
         generate_regex_pattern(&test_util::create_predicate_set(patterns), ".*")
     }
+
+    #[test]
+    fn match_mask_agrees_with_lookups() {
+        let mut matcher = test_regex_matcher(&[&["foo"], &["ba", "*"], &["*", "qux"], &["[1-9]"]]);
+        let tokens = ["foo", "fo", "bar", "b", "aqux", "quxa", "5", "10", ""];
+
+        let mask = matcher.match_mask(&tokens);
+
+        assert_eq!(mask.len(), tokens.len());
+        for (index, token) in tokens.iter().enumerate() {
+            assert_eq!(
+                mask.contains(index),
+                test_util::matches(&mut matcher, token),
+                "{}",
+                token
+            );
+        }
+        assert_eq!(mask.count_ones(..), 4);
+    }
+
+    #[test]
+    fn case_insensitive_match_mask_agrees_with_lookups() {
+        let mut matcher = test_regex_matcher(&[&["foo"], &["ba", "*"], &["*", "qux"], &["[1-9]"]]);
+        matcher.set_case_insensitive(true);
+        let tokens = ["FOO", "Foo", "BAR", "AQUX", "fo"];
+
+        let mask = matcher.match_mask(&tokens);
+
+        assert_eq!(mask.len(), tokens.len());
+        for (index, token) in tokens.iter().enumerate() {
+            assert_eq!(
+                mask.contains(index),
+                test_util::matches(&mut matcher, token),
+                "{}",
+                token
+            );
+        }
+        assert_eq!(mask.count_ones(..), 4);
+    }
+
+    #[test]
+    fn invalidated_term_is_looked_up_again() {
+        let mut matcher = test_regex_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        for _ in 0..2 {
+            matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+            matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        }
+        assert_eq!(term_db.calls(), 2);
+
+        matcher.invalidate_term("foo1");
+
+        matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn transferred_cache_saves_doc_freq_calls_after_rebuild() {
+        let mut old_matcher = test_regex_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        old_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        old_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        let mut new_matcher = test_regex_matcher(&[&["foo", "*"], &["*", "bar"]]);
+        new_matcher.transfer_cache(&old_matcher);
+
+        new_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        new_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        new_matcher.lookup_doc_freq_reciprocal("xbar", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn matched_tokens_with_unknown_doc_freq_get_the_default_reciprocal() {
+        let mut matcher = test_regex_matcher(&[&["foo", "*"]]);
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        // Also for the cached doc freq:
+        for _ in 0..2 {
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.1))
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo0", &test_util::SegmentTermDb),
+                None
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo1", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.5))
+            );
+        }
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("bar?", &test_util::SegmentTermDb),
+            None
+        );
+    }
+
+    #[test]
+    fn terms_missing_from_the_doc_freq_map_get_the_default_reciprocal() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo?"]]);
+        let mut matcher = RegexMatcher::new(
+            compile_regex(&predicate_set).unwrap(),
+            &predicate_set,
+            &HashMap::new(),
+        );
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+            Some(DocFreqReciprocal(0.1))
+        );
+    }
+
+    #[test]
+    fn unknown_doc_freq_gets_the_default_reciprocal_unlike_zero_doc_freq() {
+        let mut matcher = test_regex_matcher(&[&["foo", "*"]]);
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+            Some(DocFreqReciprocal(0.1))
+        );
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("foo0", &test_util::SegmentTermDb),
+            None
+        );
+
+        // A doc freq of 0 from `get_doc_freq` is never taken to be unknown:
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("foobar", &test_util::ZeroTermDb),
+            None
+        );
+    }
+
+    #[test]
+    fn compile_regex_sorted_matches_like_the_set_path() {
+        let predicate_set =
+            test_util::create_predicate_set(&[&["foo"], &["ba", "*"], &["*", "qux"], &["[1-9]"]]);
+        let predicates: Vec<MatchPredicate> = predicate_set.iter().cloned().collect();
+
+        let set_regex = compile_regex(&predicate_set).unwrap();
+        let sorted_regex = compile_regex_sorted(&predicates).unwrap();
+        assert_eq!(sorted_regex.as_str(), set_regex.as_str());
+
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+        let mut set_matcher =
+            RegexMatcher::new(set_regex, &predicate_set, &term_doc_freq_reciprocals);
+        let mut sorted_matcher = RegexMatcher::from_groups(
+            sorted_regex,
+            &GroupedPatterns::group_sorted(&predicates),
+            &term_doc_freq_reciprocals,
+        );
+
+        for token in &["foo", "bar", "aqux", "5", "fo", "10"] {
+            assert_eq!(
+                sorted_matcher.lookup_doc_freq_reciprocal(token, &test_util::AnyTermDb),
+                set_matcher.lookup_doc_freq_reciprocal(token, &test_util::AnyTermDb),
+                "{}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn group_matchers_match_like_the_monolithic_matcher() {
+        let patterns: &[&[&str]] = &[
+            &["foo"],
+            &["ba", "*"],
+            &["b", "*", "z"],
+            &["[1-100]"],
+            &["*", "qux"],
+            &["*", "ux", "*"],
+        ];
+        let mut predicate_set = test_util::create_predicate_set(patterns);
+        predicate_set.insert(MatchPredicate::Raw("x[0-9]+y".to_string()));
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+
+        let mut group_matchers =
+            compile_by_group(&predicate_set, &term_doc_freq_reciprocals).unwrap();
+        let mut monolithic_matcher = RegexMatcher::new(
+            compile_regex(&predicate_set).unwrap(),
+            &predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+
+        assert!(group_matchers.terms.is_some());
+        assert!(group_matchers.terms_wc.is_some());
+        assert!(group_matchers.terms_internal_wc.is_some());
+        assert!(group_matchers.wc_terms.is_some());
+        assert!(group_matchers.wc_terms_wc.is_some());
+        assert!(group_matchers.raw.is_some());
+
+        assert!(test_util::matches(
+            group_matchers.terms.as_mut().unwrap(),
+            "foo"
+        ));
+        assert!(!test_util::matches(
+            group_matchers.terms.as_mut().unwrap(),
+            "bar"
+        ));
+
+        for token in &[
+            "foo", "fo", "bar", "ba", "bz", "bxz", "50", "101", "qux", "aqux", "uxa", "x12y", "xy",
+            "zzz", "",
+        ] {
+            assert_eq!(
+                test_util::matches(&mut group_matchers, token),
+                test_util::matches(&mut monolithic_matcher, token),
+                "{}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn empty_predicate_set_has_no_group_matchers() {
+        let mut group_matchers =
+            compile_by_group(&MatchPredicateSet::new(), &HashMap::new()).unwrap();
+
+        assert!(group_matchers.terms.is_none());
+        assert!(group_matchers.raw.is_none());
+        assert!(!test_util::matches(&mut group_matchers, "foo"));
+    }
+
+    #[test]
+    fn case_insensitive_match_spans_cover_the_original_token() {
+        let mut matcher = test_regex_matcher(&[&["foo", "*"], &["*", "bar"], &["qux"]]);
+        matcher.set_case_insensitive(true);
+
+        assert!(test_util::matches(&mut matcher, "FooBar"));
+        assert_eq!(matcher.last_match_span(0), Some((0, 6)));
+
+        // Lowercasing 'ẞ' makes it one byte shorter:
+        let token = "\u{1e9e}BAR";
+        assert!(test_util::matches(&mut matcher, token));
+        let (start, end) = matcher.last_match_span(0).unwrap();
+        assert_eq!(&token[start..end], token);
+
+        assert!(test_util::matches(&mut matcher, "QUX"));
+        assert_eq!(matcher.last_match_span(0), Some((0, 3)));
+        assert_eq!(matcher.last_match_span(1), Some((0, 3)));
+
+        matcher.set_case_insensitive(false);
+        assert!(!test_util::matches(&mut matcher, "FooBar"));
+    }
+
+    #[test]
+    fn case_insensitive_full_match_agrees_with_lookup() {
+        let mut matcher = test_regex_matcher(&[&["foo"], &["ba", "*"]]);
+        matcher.set_case_insensitive(true);
+
+        assert!(test_util::matches(&mut matcher, "FOO"));
+        assert_eq!(matcher.is_full_match("FOO"), Some(true));
+        assert_eq!(matcher.is_full_match("BAR"), Some(true));
+        assert_eq!(matcher.is_full_match("QUX"), None);
+
+        matcher.set_case_insensitive(false);
+        assert_eq!(matcher.is_full_match("FOO"), None);
+    }
+
+    #[test]
+    fn lookup_normalized_returns_the_case_folded_token() {
+        let mut matcher = test_regex_matcher(&[&["foo", "*"]]);
+        matcher.set_case_insensitive(true);
+
+        let (doc_freq_reciprocal, normalized) =
+            matcher.lookup_normalized("FooBAR", &test_util::AnyTermDb);
+        assert!(doc_freq_reciprocal.is_some());
+        assert_eq!(normalized, "foobar");
+        assert!(matches!(normalized, Cow::Owned(_)));
+
+        let (doc_freq_reciprocal, normalized) =
+            matcher.lookup_normalized("QUX", &test_util::AnyTermDb);
+        assert!(doc_freq_reciprocal.is_none());
+        assert_eq!(normalized, "qux");
+
+        // Already normalized:
+        let (_, normalized) = matcher.lookup_normalized("foobar", &test_util::AnyTermDb);
+        assert!(matches!(normalized, Cow::Borrowed("foobar")));
+
+        matcher.set_case_insensitive(false);
+        let (doc_freq_reciprocal, normalized) =
+            matcher.lookup_normalized("FooBAR", &test_util::AnyTermDb);
+        assert!(doc_freq_reciprocal.is_none());
+        assert!(matches!(normalized, Cow::Borrowed("FooBAR")));
+    }
+
+    #[test]
+    fn wide_single_char_term_alternation_becomes_a_class() {
+        let terms: Vec<String> = (0..3000)
+            .map(|i| std::char::from_u32(0x4e00 + i).unwrap().to_string())
+            .chain(vec!["foo".to_string(), "bar".to_string()])
+            .collect();
+        let patterns: Vec<[&str; 1]> = terms.iter().map(|term| [term.as_str()]).collect();
+        let patterns: Vec<&[&str]> = patterns.iter().map(|pattern| &pattern[..]).collect();
+
+        let pattern = test_generate_regex_pattern(&patterns);
+        assert_eq!(pattern, "^(bar)$|^(foo)$|^[\u{4e00}-\u{59b7}]$");
+
+        let mut matcher = test_regex_matcher(&patterns);
+        let term_db = test_util::CountingTermDb::default();
+        for token in &["\u{4e00}", "\u{4e01}", "\u{59b7}", "foo"] {
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal(token, &term_db),
+                DocFreqReciprocal::from_doc_freq(1),
+                "{}",
+                token
+            );
+        }
+        assert!(!test_util::matches(&mut matcher, "\u{59b8}"));
+        assert_eq!(term_db.calls(), 0);
+
+        // Term predicates are not affected by invalidation:
+        matcher.invalidate_term("\u{4e00}");
+        matcher.invalidate_term("foo");
+        for token in &["\u{4e00}", "foo"] {
+            assert!(matcher
+                .lookup_doc_freq_reciprocal(token, &term_db)
+                .is_some());
+        }
+        assert_eq!(term_db.calls(), 0);
+    }
+
+    #[test]
+    fn is_full_match_distinguishes_partial_matches() {
+        let mut matcher = test_regex_matcher(&[&["foo"], &["ba", "*"], &["*", "qu", "*"]]);
+
+        // term
+        assert_eq!(matcher.is_full_match("foo"), Some(true));
+        assert_eq!(matcher.is_full_match("fooo"), Some(false));
+        assert_eq!(matcher.is_full_match("fo"), None);
+        // prefix
+        assert_eq!(matcher.is_full_match("bar"), Some(true));
+        assert_eq!(matcher.is_full_match("bar\u{1f600}"), Some(false));
+        // substring
+        assert_eq!(matcher.is_full_match("aqua"), Some(true));
+        assert_eq!(matcher.is_full_match("qu\u{1f600}"), Some(false));
+        assert_eq!(matcher.is_full_match("\u{1f600}qu"), None);
+        assert_eq!(matcher.is_full_match("\u{1f600}qux"), None);
+    }
+
+    #[test]
+    fn compile_regex_with_config_can_raise_size_limit() {
+        let patterns: Vec<[String; 3]> = (0..20000)
+            .map(|i| [format!("w{}", i), "*".to_string(), format!("x{}", i)])
+            .collect();
+        let patterns: Vec<Vec<&str>> = patterns
+            .iter()
+            .map(|pattern| pattern.iter().map(String::as_str).collect())
+            .collect();
+        let patterns: Vec<&[&str]> = patterns.iter().map(Vec::as_slice).collect();
+        let predicate_set = test_util::create_predicate_set(&patterns);
+
+        assert!(compile_regex(&predicate_set).is_err());
+
+        let regex = compile_regex_with_config(
+            &predicate_set,
+            &RegexConfig {
+                size_limit: Some(100 * (1 << 20)),
+                ..RegexConfig::default()
+            },
+        )
+        .unwrap();
+        assert!(regex.is_match("w19999abcx19999"));
+
+        // The regexes compiled by `is_full_match` get the raised limit too:
+        let mut matcher = RegexMatcher::new(
+            regex,
+            &predicate_set,
+            &test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set),
+        );
+        matcher.set_size_limit(Some(100 * (1 << 20)));
+        assert_eq!(matcher.is_full_match("w19999abcx19999"), Some(true));
+        assert_eq!(
+            matcher.is_full_match("w19999abcx19999\u{1f600}"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn compile_regex_with_config_without_unicode_has_ascii_wildcards() {
+        let predicate_set = test_util::create_predicate_set(&[&["sm", "*", "rbrod"]]);
+        let regex = compile_regex_with_config(
+            &predicate_set,
+            &RegexConfig {
+                unicode: false,
+                ..RegexConfig::default()
+            },
+        )
+        .unwrap();
+
+        assert!(regex.is_match("smaaarbrod"));
+        assert!(!regex.is_match("smøøørbrod"));
+    }
+
+    #[test]
+    fn ascii_alphabet_is_used_only_for_ascii_literals() {
+        let ascii_config = RegexConfig {
+            unicode: false,
+            ..RegexConfig::default()
+        };
+
+        let ascii_set = test_util::create_predicate_set(&[&["sm", "*", "rbrod"]]);
+        assert!(ascii_config.uses_ascii_alphabet(&GroupedPatterns::group(&ascii_set)));
+        assert!(!RegexConfig::default().uses_ascii_alphabet(&GroupedPatterns::group(&ascii_set)));
+
+        let unicode_set = test_util::create_predicate_set(&[&["smør", "*", "brod"]]);
+        assert!(!ascii_config.uses_ascii_alphabet(&GroupedPatterns::group(&unicode_set)));
+
+        // Falls back to Unicode instead of failing, still with ASCII wildcards:
+        let regex = compile_regex_with_config(&unicode_set, &ascii_config).unwrap();
+        assert!(regex.is_match("smørrebrod"));
+        assert!(!regex.is_match("smørrøbrod"));
+    }
+
+    #[test]
+    fn raw_predicates_fall_back_to_unicode_alphabet() {
+        let ascii_config = RegexConfig {
+            unicode: false,
+            ..RegexConfig::default()
+        };
+
+        for (raw_expr, raw_token) in &[(".+x", "øx"), ("é+", "éé")] {
+            let mut predicate_set = test_util::create_predicate_set(&[&["foo", "*"]]);
+            predicate_set.insert(MatchPredicate::Raw(raw_expr.to_string()));
+
+            assert!(!ascii_config.uses_ascii_alphabet(&GroupedPatterns::group(&predicate_set)));
+
+            let regex = compile_regex_with_config(&predicate_set, &ascii_config).unwrap();
+            assert!(regex.is_match("foobar"));
+            assert!(regex.is_match(raw_token));
+        }
+    }
+
+    #[test]
+    fn lazy_wildcards_match_the_same_tokens_with_shorter_spans() {
+        let predicate_set =
+            test_util::create_predicate_set(&[&["a", "*", "b"], &["c", "*"], &["*", "d"]]);
+        let greedy_regex = compile_regex(&predicate_set).unwrap();
+        let lazy_regex = compile_regex_with_config(
+            &predicate_set,
+            &RegexConfig {
+                wildcard_quantifier: WildcardQuantifier::Lazy,
+                ..RegexConfig::default()
+            },
+        )
+        .unwrap();
+
+        for token in &["ab", "axbxb", "axbx", "c", "cxx", "xd", "xdd", "x"] {
+            assert_eq!(
+                lazy_regex.is_match(token),
+                greedy_regex.is_match(token),
+                "{}",
+                token
+            );
+        }
+
+        // The trailing wildcard of a prefix pattern is not anchored to the end of the token:
+        let span = |regex: &regex::Regex| regex.find("cxx").unwrap().range();
+        assert_eq!(span(&greedy_regex), 0..3);
+        assert_eq!(span(&lazy_regex), 0..1);
+    }
+
+    #[test]
+    fn lazy_wildcards_do_not_change_full_matches() {
+        let patterns: &[&[&str]] = &[&["foo"], &["ba", "*"], &["*", "qu", "*"]];
+        let predicate_set = test_util::create_predicate_set(patterns);
+        let lazy_regex = compile_regex_with_config(
+            &predicate_set,
+            &RegexConfig {
+                wildcard_quantifier: WildcardQuantifier::Lazy,
+                ..RegexConfig::default()
+            },
+        )
+        .unwrap();
+        let mut lazy_matcher = RegexMatcher::new(
+            lazy_regex,
+            &predicate_set,
+            &test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set),
+        );
+        let mut greedy_matcher = test_regex_matcher(patterns);
+
+        for token_text in &[
+            "foo",
+            "fooo",
+            "fo",
+            "bar",
+            "baxx",
+            "bar\u{1f600}",
+            "aqua",
+            "qu\u{1f600}",
+            "\u{1f600}qu",
+        ] {
+            assert_eq!(
+                lazy_matcher.is_full_match(token_text),
+                greedy_matcher.is_full_match(token_text),
+                "{}",
+                token_text
+            );
+        }
+        assert_eq!(lazy_matcher.is_full_match("baxx"), Some(true));
+    }
+
+    #[test]
+    fn last_capture_locations_reflects_most_recent_lookup() {
+        let mut matcher = test_regex_matcher(&[&["foo"], &["f", "*", "r"]]);
+
+        assert!(test_util::matches(&mut matcher, "fooxbar"));
+        assert_eq!(matcher.last_capture_locations().get(0), Some((0, 7)));
+        assert_eq!(matcher.last_capture_locations().get(1), None);
+
+        assert!(test_util::matches(&mut matcher, "foo"));
+        assert_eq!(matcher.last_capture_locations().get(0), Some((0, 3)));
+        assert_eq!(matcher.last_capture_locations().get(1), Some((0, 3)));
+    }
+
+    #[test]
+    fn compile_regex_from_modified_groups() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["bar"], &["ba", "*"]]);
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+
+        let mut groups = GroupedPatterns::group(&predicate_set);
+        groups.terms.retain(|term_text| *term_text != "foo");
+        groups.terms_wc.clear();
+
+        let mut matcher = RegexMatcher::from_groups(
+            compile_regex_from_groups(&groups, &RegexConfig::default()).unwrap(),
+            &groups,
+            &term_doc_freq_reciprocals,
+        );
+
+        assert!(!test_util::matches(&mut matcher, "foo"));
+        assert!(test_util::matches(&mut matcher, "bar"));
+        assert!(!test_util::matches(&mut matcher, "baz"));
+    }
+
+    #[test]
+    fn metrics_count_cache_hits_and_doc_freq_calls() {
+        let mut matcher = test_regex_matcher(&[&["foo"], &["ba", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        for token in &["foo", "bar", "qux", "bar", "baz", "foo"] {
+            matcher.lookup_doc_freq_reciprocal(token, &term_db);
+        }
+
+        assert_eq!(
+            matcher.metrics(),
+            &Metrics {
+                lookups: 6,
+                matches: 5,
+                cache_hits: 3,
+                cache_misses: 2,
+                get_doc_freq_calls: 2,
+            }
+        );
+        assert_eq!(term_db.calls(), 2);
+    }
+
+    #[test]
+    fn num_range_matches_integers_within_range() {
+        let mut matcher = test_regex_matcher(&[&["[10-20]"]]);
+
+        assert!(test_util::matches(&mut matcher, "15"));
+        assert!(test_util::matches(&mut matcher, "10"));
+        assert!(test_util::matches(&mut matcher, "20"));
+        assert!(!test_util::matches(&mut matcher, "5"));
+        assert!(!test_util::matches(&mut matcher, "25"));
+        assert!(!test_util::matches(&mut matcher, "150"));
+    }
+
+    #[test]
+    fn num_range_combines_with_literals_and_wildcards() {
+        let mut matcher = test_regex_matcher(&[&["v", "[1-3]"], &["t", "*", "[-9--1]"]]);
+
+        assert!(test_util::matches(&mut matcher, "v2"));
+        assert!(!test_util::matches(&mut matcher, "v4"));
+        assert!(test_util::matches(&mut matcher, "temp-9"));
+        assert!(!test_util::matches(&mut matcher, "temp9"));
+    }
+
+    #[test]
+    fn reject_empty_matching_rejects_a_lone_wildcard() {
+        let options = CompileOptions {
+            reject_empty_matching: true,
+            ..CompileOptions::default()
+        };
+
+        let rejected = test_util::create_predicate_set(&[&["*"]]);
+        assert_eq!(
+            compile_regex_with_options(&rejected, &options).err(),
+            Some(format!(
+                "compile_regex: predicate {:?} matches the empty string",
+                rejected.iter().next().unwrap()
+            ))
+        );
+
+        let accepted = test_util::create_predicate_set(&[&["foo", "*"]]);
+        let regex = compile_regex_with_options(&accepted, &options).unwrap();
+        assert!(regex.is_match("foobar"));
+        assert!(!regex.is_match(""));
+
+        assert!(compile_regex_with_options(&rejected, &CompileOptions::default()).is_ok());
+    }
 }
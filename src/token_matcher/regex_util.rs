@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
 use crate::PatternASTNode;
 use super::*;
 
@@ -5,12 +8,19 @@ use super::*;
 /// Patterns grouped into 5 groups:
 /// 1. terms (no wildcards)
 /// 2. terms_wc: ends with a wildcard, but does not start with a wildcard
+///    (prefix_terms are terms with prefix semantics, belonging to the same group)
 /// 3. terms_internal_wc: does not start nor end with a wildcard, but has internal wildcards
+///    (or other non-literal nodes, like numeric ranges or repeats)
 /// 4. wc_terms: starts with a wildcard, but does not end with a wildcard
 /// 5. wc_terms_wc: starts and ends with a wildcard
 ///
+/// Raw regex expressions are kept as they are, in a separate group.
+///
 /// the groups have their wildcard at start/end stripped away.
 ///
+/// Patterns that match everything (see `PatternAST::is_match_all`) are not put in any group,
+/// but set the `match_all` flag instead.
+///
 /// This grouping is done in order to optimize automaton compile times, where we can group
 /// together various wildcards.
 /// e.g.:
@@ -19,58 +29,1358 @@ use super::*;
 #[derive(Default)]
 pub struct GroupedPatterns<'a> {
     pub terms: Vec<&'a str>,
+    pub prefix_terms: Vec<&'a str>,
     pub terms_wc: Vec<&'a [PatternASTNode]>,
     pub terms_internal_wc: Vec<&'a [PatternASTNode]>,
     pub wc_terms: Vec<&'a [PatternASTNode]>,
     pub wc_terms_wc: Vec<&'a [PatternASTNode]>,
+    pub raw: Vec<&'a str>,
+    pub match_all: bool,
 }
 
 impl<'a> GroupedPatterns<'a> {
     pub fn group(predicate_set: &'a BTreeSet<MatchPredicate>) -> Self {
+        Self::group_iter(predicate_set)
+    }
+
+    ///
+    /// Group predicates that are already sorted and deduplicated,
+    /// saving the cost of building a `BTreeSet`.
+    /// The order is only checked in debug builds.
+    ///
+    pub fn group_sorted(predicates: &'a [MatchPredicate]) -> Self {
+        debug_assert!(
+            predicates.windows(2).all(|pair| pair[0] < pair[1]),
+            "group_sorted: predicates must be sorted and unique"
+        );
+
+        Self::group_iter(predicates)
+    }
+
+    fn group_iter(predicates: impl IntoIterator<Item = &'a MatchPredicate>) -> Self {
         let mut groups = GroupedPatterns::default();
 
-        for match_predicate in predicate_set {
-            match match_predicate {
-                MatchPredicate::Term(term_text) => {
+        for match_predicate in predicates {
+            let group = match PatternGroup::of(match_predicate) {
+                Some(group) => group,
+                None => continue,
+            };
+
+            match (group, match_predicate) {
+                (PatternGroup::Terms, MatchPredicate::Term(term_text, _)) => {
                     groups.terms.push(term_text);
                 }
-                MatchPredicate::Pattern(ast) => {
-                    let nodes = &ast.0;
-                    match nodes.first() {
-                        Some(PatternASTNode::Literal(first_text)) => {
-                            if nodes.len() == 1 {
-                                groups.terms.push(first_text);
-                            } else {
-                                match nodes.last() {
-                                    Some(PatternASTNode::Literal(_)) => {
-                                        groups.terms_internal_wc.push(nodes);
-                                    }
-                                    Some(PatternASTNode::Wildcard) => {
-                                        groups.terms_wc.push(&nodes[..nodes.len() - 1]);
-                                    }
-                                    None => {}
-                                }
-                            }
+                (PatternGroup::Terms, MatchPredicate::Pattern(ast)) => match ast.0.as_slice() {
+                    [PatternASTNode::Literal(text)] => groups.terms.push(text),
+                    _ => unreachable!("group_iter: {:?} is not a single literal", ast),
+                },
+                (PatternGroup::TermsWc, MatchPredicate::Term(term_text, _)) => {
+                    groups.prefix_terms.push(term_text);
+                }
+                (PatternGroup::TermsWc, MatchPredicate::Pattern(ast)) => {
+                    groups.terms_wc.push(&ast.0[..ast.0.len() - 1]);
+                }
+                (PatternGroup::TermsInternalWc, MatchPredicate::Pattern(ast)) => {
+                    groups.terms_internal_wc.push(&ast.0);
+                }
+                (PatternGroup::WcTerms, MatchPredicate::Pattern(ast)) => {
+                    groups.wc_terms.push(&ast.0[1..]);
+                }
+                (PatternGroup::WcTermsWc, MatchPredicate::Pattern(ast)) if ast.is_match_all() => {
+                    groups.match_all = true;
+                }
+                (PatternGroup::WcTermsWc, MatchPredicate::Pattern(ast)) => {
+                    groups.wc_terms_wc.push(&ast.0[1..ast.0.len() - 1]);
+                }
+                (PatternGroup::Raw, MatchPredicate::Raw(expr)) => {
+                    groups.raw.push(expr);
+                }
+                (group, _) => {
+                    unreachable!("group_iter: {:?} put in {:?}", match_predicate, group)
+                }
+            }
+        }
+
+        groups
+    }
+
+    ///
+    /// Whether every literal of the terms and patterns is ASCII, so that with ASCII wildcards
+    /// they can be compiled for an ASCII alphabet instead of Unicode.
+    /// Raw expressions may match non-ASCII characters (e.g. with `.`),
+    /// so any raw expression makes this `false`.
+    ///
+    pub fn is_ascii(&self) -> bool {
+        let nodes_are_ascii = |patterns: &[&[PatternASTNode]]| {
+            patterns.iter().all(|nodes| nodes.iter().all(node_is_ascii))
+        };
+
+        self.raw.is_empty()
+            && self.terms.iter().all(|term_text| term_text.is_ascii())
+            && self
+                .prefix_terms
+                .iter()
+                .all(|term_text| term_text.is_ascii())
+            && nodes_are_ascii(&self.terms_wc)
+            && nodes_are_ascii(&self.terms_internal_wc)
+            && nodes_are_ascii(&self.wc_terms)
+            && nodes_are_ascii(&self.wc_terms_wc)
+    }
+}
+
+fn node_is_ascii(node: &PatternASTNode) -> bool {
+    match node {
+        PatternASTNode::Literal(text) => text.is_ascii(),
+        PatternASTNode::Repeat { node, .. } => node_is_ascii(node),
+        PatternASTNode::Wildcard | PatternASTNode::NumRange { .. } => true,
+    }
+}
+
+///
+/// One of the groups of `GroupedPatterns`.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum PatternGroup {
+    Terms,
+    /// Includes prefix terms
+    TermsWc,
+    TermsInternalWc,
+    WcTerms,
+    /// Includes patterns matching everything
+    WcTermsWc,
+    Raw,
+}
+
+impl PatternGroup {
+    ///
+    /// The group a predicate is put in by `GroupedPatterns::group`.
+    /// Empty patterns match nothing, and are not put in any group.
+    ///
+    pub fn of(match_predicate: &MatchPredicate) -> Option<Self> {
+        match match_predicate {
+            MatchPredicate::Term(_, TermSemantics::Exact) => Some(PatternGroup::Terms),
+            MatchPredicate::Term(_, TermSemantics::Prefix) => Some(PatternGroup::TermsWc),
+            MatchPredicate::Raw(_) => Some(PatternGroup::Raw),
+            MatchPredicate::Pattern(ast) if ast.is_match_all() => Some(PatternGroup::WcTermsWc),
+            MatchPredicate::Pattern(ast) => {
+                let nodes = &ast.0;
+                let starts_with_wc = nodes.first() == Some(&PatternASTNode::Wildcard);
+                let ends_with_wc =
+                    nodes.len() > 1 && nodes.last() == Some(&PatternASTNode::Wildcard);
+
+                match nodes.as_slice() {
+                    [] => None,
+                    [PatternASTNode::Literal(_)] => Some(PatternGroup::Terms),
+                    _ => Some(match (starts_with_wc, ends_with_wc) {
+                        (false, false) => PatternGroup::TermsInternalWc,
+                        (false, true) => PatternGroup::TermsWc,
+                        (true, false) => PatternGroup::WcTerms,
+                        (true, true) => PatternGroup::WcTermsWc,
+                    }),
+                }
+            }
+        }
+    }
+}
+
+///
+/// Summary of a predicate set, see `profile`.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PredicateProfile {
+    pub terms: usize,
+    pub prefix_terms: usize,
+    pub terms_wc: usize,
+    pub terms_internal_wc: usize,
+    pub wc_terms: usize,
+    pub wc_terms_wc: usize,
+    pub raw: usize,
+    pub match_all: bool,
+    /// Total bytes of literal text in terms and patterns (raw expressions not included)
+    pub literal_bytes: usize,
+    /// The number of predicates in the largest group, each group being compiled into one alternation
+    pub max_alternation_width: usize,
+}
+
+///
+/// Profile a predicate set before compiling it, e.g. for choosing a backend or planning capacity.
+///
+pub fn profile(predicate_set: &MatchPredicateSet) -> PredicateProfile {
+    let groups = GroupedPatterns::group(predicate_set);
+
+    let literal_bytes = predicate_set
+        .iter()
+        .map(|match_predicate| match match_predicate {
+            MatchPredicate::Term(term_text, _) => term_text.len(),
+            MatchPredicate::Pattern(ast) => ast.0.iter().map(literal_byte_count).sum(),
+            MatchPredicate::Raw(_) => 0,
+        })
+        .sum();
+
+    let group_widths = [
+        groups.terms.len(),
+        groups.prefix_terms.len(),
+        groups.terms_wc.len(),
+        groups.terms_internal_wc.len(),
+        groups.wc_terms.len(),
+        groups.wc_terms_wc.len(),
+        groups.raw.len(),
+    ];
+
+    PredicateProfile {
+        terms: groups.terms.len(),
+        prefix_terms: groups.prefix_terms.len(),
+        terms_wc: groups.terms_wc.len(),
+        terms_internal_wc: groups.terms_internal_wc.len(),
+        wc_terms: groups.wc_terms.len(),
+        wc_terms_wc: groups.wc_terms_wc.len(),
+        raw: groups.raw.len(),
+        match_all: groups.match_all,
+        literal_bytes,
+        max_alternation_width: group_widths.iter().copied().max().unwrap_or(0),
+    }
+}
+
+fn literal_byte_count(node: &PatternASTNode) -> usize {
+    match node {
+        PatternASTNode::Literal(text) => text.len(),
+        PatternASTNode::Repeat { node, .. } => literal_byte_count(node),
+        PatternASTNode::Wildcard | PatternASTNode::NumRange { .. } => 0,
+    }
+}
+
+///
+/// The regex for a wildcard, shared by all backends:
+/// any number of characters up to and including Latin Extended-B.
+///
+/// It is embedded once per wildcard of every pattern, so it is written as compactly as possible.
+/// Neither `regex` nor `regex-automata` supports subroutine calls or other references
+/// to an earlier group, so the class can't be defined once and reused by every wildcard.
+///
+pub const WILDCARD_EXPR: &str = r#"[\x00-\u024f]*"#;
+
+///
+/// The regex for a wildcard when matching ASCII only.
+///
+pub const ASCII_WILDCARD_EXPR: &str = r#"[\x00-\x7f]*"#;
+
+///
+/// The last character matched by `WILDCARD_EXPR`.
+///
+pub const WILDCARD_MAX_CHAR: char = '\u{24f}';
+
+///
+/// The last character matched by `ASCII_WILDCARD_EXPR`.
+///
+pub const ASCII_WILDCARD_MAX_CHAR: char = '\x7f';
+
+///
+/// How a wildcard quantifies the characters it matches.
+///
+/// Only the regex backend honors this, where it decides the spans of capture groups
+/// and of unanchored (e.g. raw) expressions.
+/// The DFA backends (automaton and tagged) and the set backend only decide whether
+/// a whole token matches, which does not depend on the quantifier.
+///
+/// Possessive quantifiers are not supported by the `regex` crate, which never backtracks anyway.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WildcardQuantifier {
+    /// As many characters as possible, like `.*`
+    #[default]
+    Greedy,
+    /// As few characters as possible, like `.*?`
+    Lazy,
+}
+
+impl WildcardQuantifier {
+    ///
+    /// The regex for a wildcard quantified this way, see `WILDCARD_EXPR` and `ASCII_WILDCARD_EXPR`.
+    ///
+    pub fn wildcard_expr(self, unicode: bool) -> Cow<'static, str> {
+        let greedy_expr = if unicode {
+            WILDCARD_EXPR
+        } else {
+            ASCII_WILDCARD_EXPR
+        };
+
+        match self {
+            WildcardQuantifier::Greedy => Cow::Borrowed(greedy_expr),
+            WildcardQuantifier::Lazy => Cow::Owned(format!("{}?", greedy_expr)),
+        }
+    }
+}
+
+///
+/// Limits on the predicates to compile, protecting against adversarial or accidental blowup
+/// of compile time and automaton size. Everything is unlimited by default.
+///
+/// The limits are enforced by `compile_automaton_with_options`, `compile_regex_with_options`,
+/// `compile_regex_set_with_options` and `compile_tagged_automaton_with_options`
+/// (the backend most prone to blowup by many wildcards). The other compile functions ignore them.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CompileOptions {
+    /// The maximum number of wildcards in a single pattern,
+    /// e.g. `a*b*c*d*e` has 4 (adjacent wildcards count as one)
+    pub max_wildcards_per_pattern: Option<usize>,
+    /// Reject predicates that match the empty string, e.g. `*`,
+    /// for pipelines where an empty token should match nothing
+    pub reject_empty_matching: bool,
+    /// Wildcards only match ASCII characters (see `ASCII_WILDCARD_EXPR`).
+    /// When every literal is ASCII too, the automaton is built for an ASCII alphabet,
+    /// otherwise it falls back to Unicode.
+    /// The regex backend does the same (see `RegexConfig::unicode`), the set backend ignores this
+    pub ascii_wildcards: bool,
+}
+
+impl CompileOptions {
+    ///
+    /// Check a predicate set against the limits before compiling it.
+    ///
+    pub fn check(&self, predicate_set: &MatchPredicateSet) -> Result<(), String> {
+        if self.reject_empty_matching {
+            if let Some(match_predicate) = predicate_set.iter().find(|p| matches_empty(p)) {
+                return Err(format!(
+                    "predicate {:?} matches the empty string",
+                    match_predicate
+                ));
+            }
+        }
+
+        if let Some(max_wildcards) = self.max_wildcards_per_pattern {
+            for match_predicate in predicate_set {
+                if let MatchPredicate::Pattern(ast) = match_predicate {
+                    let wildcards: usize = ast.normalize().0.iter().map(wildcard_count).sum();
+                    if wildcards > max_wildcards {
+                        return Err(format!(
+                            "pattern {} has {} wildcards, the maximum is {}",
+                            ast, wildcards, max_wildcards
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// Whether a predicate matches the empty string.
+/// Unparsable raw expressions are assumed not to, they fail when compiled anyway.
+///
+fn matches_empty(match_predicate: &MatchPredicate) -> bool {
+    match match_predicate {
+        MatchPredicate::Term(term_text, _) => term_text.is_empty(),
+        MatchPredicate::Pattern(ast) => ast.0.iter().all(node_matches_empty),
+        MatchPredicate::Raw(expr) => regex::Regex::new(&format!("^(?:{})$", expr))
+            .map(|regex| regex.is_match(""))
+            .unwrap_or(false),
+    }
+}
+
+fn node_matches_empty(node: &PatternASTNode) -> bool {
+    match node {
+        PatternASTNode::Literal(text) => text.is_empty(),
+        PatternASTNode::Wildcard => true,
+        PatternASTNode::NumRange { .. } => false,
+        PatternASTNode::Repeat { node, min, .. } => *min == 0 || node_matches_empty(node),
+    }
+}
+
+///
+/// The number of wildcards in a pattern node.
+///
+pub fn wildcard_count(node: &PatternASTNode) -> usize {
+    match node {
+        PatternASTNode::Wildcard => 1,
+        PatternASTNode::Repeat { node, .. } => wildcard_count(node),
+        PatternASTNode::Literal(_) | PatternASTNode::NumRange { .. } => 0,
+    }
+}
+
+///
+/// How predicates are anchored to the token.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AnchorSpec {
+    /// The whole token must match a predicate
+    #[default]
+    Exact,
+    /// The token must start with a predicate, e.g. for autocomplete
+    PrefixSearch,
+    /// The token must end with a predicate, e.g. for reverse autocomplete of domain suffixes
+    SuffixSearch,
+}
+
+impl AnchorSpec {
+    ///
+    /// Rewrite a predicate set so that every predicate is anchored according to this spec,
+    /// by adding a wildcard to the end of each predicate that should be left open.
+    ///
+    /// The result works with every backend, and terms become patterns:
+    /// their doc freq is looked up per matching token, not per query term.
+    ///
+    /// Raw predicates are left as they are.
+    ///
+    pub fn apply(self, predicate_set: &MatchPredicateSet) -> MatchPredicateSet {
+        predicate_set
+            .iter()
+            .map(|match_predicate| {
+                let mut nodes = match match_predicate {
+                    MatchPredicate::Raw(_) => return match_predicate.clone(),
+                    MatchPredicate::Term(term_text, TermSemantics::Exact) => {
+                        vec![PatternASTNode::Literal(term_text.clone())]
+                    }
+                    MatchPredicate::Term(term_text, TermSemantics::Prefix) => vec![
+                        PatternASTNode::Literal(term_text.clone()),
+                        PatternASTNode::Wildcard,
+                    ],
+                    MatchPredicate::Pattern(ast) => ast.0.clone(),
+                };
+
+                match self {
+                    AnchorSpec::Exact => return match_predicate.clone(),
+                    AnchorSpec::PrefixSearch => {
+                        if nodes.last() != Some(&PatternASTNode::Wildcard) {
+                            nodes.push(PatternASTNode::Wildcard);
                         }
-                        Some(PatternASTNode::Wildcard) => {
-                            if nodes.len() > 1 {
-                                match nodes.last() {
-                                    Some(PatternASTNode::Literal(_)) => {
-                                        groups.wc_terms.push(&nodes[1..]);
-                                    }
-                                    Some(PatternASTNode::Wildcard) => {
-                                        groups.wc_terms_wc.push(&nodes[1..nodes.len() - 1]);
-                                    }
-                                    None => {}
-                                }
-                            }
+                    }
+                    AnchorSpec::SuffixSearch => {
+                        if nodes.first() != Some(&PatternASTNode::Wildcard) {
+                            nodes.insert(0, PatternASTNode::Wildcard);
                         }
-                        None => {}
                     }
                 }
+
+                MatchPredicate::Pattern(crate::PatternAST(nodes))
+            })
+            .collect()
+    }
+}
+
+///
+/// Trie of characters, for sharing common prefixes or suffixes between literals.
+///
+#[derive(Default)]
+struct CharTrie {
+    children: BTreeMap<char, CharTrie>,
+    terminal: bool,
+}
+
+impl CharTrie {
+    fn insert(&mut self, chars: impl Iterator<Item = char>) {
+        let mut node = self;
+        for c in chars {
+            node = node.children.entry(c).or_default();
+        }
+        node.terminal = true;
+    }
+
+    ///
+    /// Alternatives for a trie built from reversed literals.
+    /// Each alternative is the regex for the _preceding_ characters, followed by the edge character.
+    ///
+    fn suffix_alternatives(&self) -> Vec<String> {
+        self.children
+            .iter()
+            .map(|(c, child)| {
+                let mut expr = group_alternatives(child.suffix_alternatives(), child.terminal);
+                expr.push_str(&regex_syntax::escape(&c.to_string()));
+                expr
+            })
+            .collect()
+    }
+
+    ///
+    /// Alternatives for a trie of literals that are all followed by a wildcard
+    /// matching characters up to `wildcard_max_char`.
+    /// Below a terminal node, subtrees are dropped when the wildcard already matches
+    /// whatever their longer literals would add, and are optional otherwise.
+    ///
+    fn prefix_alternatives(&self, wildcard_max_char: char) -> Vec<String> {
+        self.children
+            .iter()
+            .filter(|(c, child)| {
+                let matched_by_wildcard =
+                    **c <= wildcard_max_char && child.has_chars_up_to(wildcard_max_char);
+                !(self.terminal && matched_by_wildcard)
+            })
+            .map(|(c, child)| {
+                let mut expr = regex_syntax::escape(&c.to_string());
+                expr.push_str(&group_alternatives(
+                    child.prefix_alternatives(wildcard_max_char),
+                    child.terminal,
+                ));
+                expr
+            })
+            .collect()
+    }
+
+    ///
+    /// Whether every character below this node is at most `max_char`.
+    ///
+    fn has_chars_up_to(&self, max_char: char) -> bool {
+        self.children
+            .iter()
+            .all(|(c, child)| *c <= max_char && child.has_chars_up_to(max_char))
+    }
+}
+
+fn group_alternatives(alternatives: Vec<String>, optional: bool) -> String {
+    match (alternatives.len(), optional) {
+        (0, _) => String::new(),
+        (1, false) => alternatives.into_iter().next().unwrap(),
+        (_, false) => format!("(?:{})", alternatives.join("|")),
+        (_, true) => format!("(?:{})?", alternatives.join("|")),
+    }
+}
+
+///
+/// Alternation of literals where literals ending the same way share the regex for that suffix,
+/// e.g. "ing", "ring" and "bring" become "(?:(?:b)?r)?ing".
+///
+/// This keeps the automaton for morphologically related suffix queries (`*ing`, `*ring`) small.
+///
+pub fn shared_suffix_regex_expr(literals: &[&str]) -> String {
+    let mut trie = CharTrie::default();
+    for literal in literals {
+        trie.insert(literal.chars().rev());
+    }
+
+    trie.suffix_alternatives().join("|")
+}
+
+///
+/// The number of terms above which single character terms are compiled into a character class,
+/// because the `regex` crate handles huge flat alternations poorly.
+///
+pub const WIDE_ALTERNATION_THRESHOLD: usize = 1000;
+
+///
+/// Split terms into those to be compiled as alternatives,
+/// and single characters to be compiled as one character class (see `char_class_regex_expr`).
+///
+/// Only a wide alternation (see `WIDE_ALTERNATION_THRESHOLD`) is split,
+/// otherwise every term stays an alternative.
+///
+pub fn split_wide_terms<'a>(terms: &[&'a str]) -> (Vec<&'a str>, Vec<char>) {
+    if terms.len() <= WIDE_ALTERNATION_THRESHOLD {
+        return (terms.to_vec(), vec![]);
+    }
+
+    let mut alternatives = vec![];
+    let mut chars = vec![];
+
+    for term in terms {
+        let mut term_chars = term.chars();
+        match (term_chars.next(), term_chars.next()) {
+            (Some(char), None) => chars.push(char),
+            _ => alternatives.push(*term),
+        }
+    }
+
+    (alternatives, chars)
+}
+
+///
+/// Character class matching any of the chars, where runs of consecutive chars become ranges,
+/// e.g. 'a', 'b', 'c', 'x' become "[a-cx]".
+///
+pub fn char_class_regex_expr(chars: &[char]) -> String {
+    let mut chars = chars.to_vec();
+    chars.sort_unstable();
+    chars.dedup();
+
+    let mut expr = "[".to_string();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let start = chars[index];
+        let mut end = start;
+        while index + 1 < chars.len() && chars[index + 1] as u32 == end as u32 + 1 {
+            index += 1;
+            end = chars[index];
+        }
+
+        expr.push_str(&regex_syntax::escape(&start.to_string()));
+        if end != start {
+            expr.push('-');
+            expr.push_str(&regex_syntax::escape(&end.to_string()));
+        }
+        index += 1;
+    }
+
+    expr.push(']');
+    expr
+}
+
+///
+/// Alternation of literals, each followed by a wildcard, sharing the regex for common prefixes,
+/// e.g. "bar", "baz", "foo", "foob" and "fooba" become "ba(?:r|z)|foo".
+///
+/// Literals extending another literal are dropped when the wildcard following the shorter one
+/// (matching characters up to `wildcard_max_char`) already matches them,
+/// otherwise they become optional, e.g. "foo" and "foo😀" become "foo(?:😀)?".
+/// The wildcard itself is not included.
+///
+pub fn shared_prefix_regex_expr(literals: &[&str], wildcard_max_char: char) -> String {
+    let mut trie = CharTrie::default();
+    for literal in literals {
+        trie.insert(literal.chars());
+    }
+
+    let alternatives = trie.prefix_alternatives(wildcard_max_char);
+
+    if trie.terminal {
+        // The empty literal followed by a wildcard matches everything in the wildcard range:
+        group_alternatives(alternatives, true)
+    } else {
+        alternatives.join("|")
+    }
+}
+
+///
+/// Regex features used by a predicate set that not every backend supports.
+///
+/// Generated expressions never use these, so only `Raw` predicates can require them.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RegexFeatures {
+    /// Anchors like `^`, `$`, `\A` or `\z`
+    pub anchors: bool,
+    /// Word boundaries like `\b` or `\B`
+    pub word_boundaries: bool,
+}
+
+impl RegexFeatures {
+    ///
+    /// Fail unless the `regex-automata` DFAs (used by the automaton backends)
+    /// support these features. The `regex` crate supports all of them.
+    ///
+    pub fn check_dfa_support(&self, backend: &str) -> Result<(), String> {
+        if self.anchors {
+            return Err(format!("{}: anchors are not supported", backend));
+        }
+        if self.word_boundaries {
+            return Err(format!("{}: word boundaries are not supported", backend));
+        }
+        Ok(())
+    }
+}
+
+///
+/// The regex features required by a predicate set.
+/// Unparsable raw expressions are skipped, they fail when compiled anyway.
+///
+pub fn required_features(predicate_set: &MatchPredicateSet) -> RegexFeatures {
+    raw_exprs_required_features(predicate_set.iter().filter_map(|match_predicate| {
+        match match_predicate {
+            MatchPredicate::Raw(expr) => Some(expr.as_str()),
+            _ => None,
+        }
+    }))
+}
+
+///
+/// The regex features required by raw expressions, e.g. those of `GroupedPatterns::raw`.
+///
+pub fn raw_exprs_required_features<'a>(exprs: impl Iterator<Item = &'a str>) -> RegexFeatures {
+    let mut features = RegexFeatures::default();
+
+    for expr in exprs {
+        if let Ok(hir) = regex_syntax::Parser::new().parse(expr) {
+            add_hir_features(&hir, &mut features);
+        }
+    }
+
+    features
+}
+
+fn add_hir_features(hir: &regex_syntax::hir::Hir, features: &mut RegexFeatures) {
+    use regex_syntax::hir::HirKind;
+
+    match hir.kind() {
+        HirKind::Anchor(_) => features.anchors = true,
+        HirKind::WordBoundary(_) => features.word_boundaries = true,
+        HirKind::Repetition(repetition) => add_hir_features(&repetition.hir, features),
+        HirKind::Group(group) => add_hir_features(&group.hir, features),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => {
+            for hir in hirs {
+                add_hir_features(hir, features);
             }
         }
+        HirKind::Empty | HirKind::Literal(_) | HirKind::Class(_) => {}
+    }
+}
 
-        groups
+///
+/// Regex expression for a single predicate, without anchors.
+///
+pub fn predicate_to_regex_expr(match_predicate: &MatchPredicate, wildcard_expr: &str) -> String {
+    match match_predicate {
+        MatchPredicate::Term(term_text, TermSemantics::Exact) => regex_syntax::escape(term_text),
+        MatchPredicate::Term(term_text, TermSemantics::Prefix) => {
+            format!("{}{}", regex_syntax::escape(term_text), wildcard_expr)
+        }
+        MatchPredicate::Raw(expr) => format!("(?:{})", expr),
+        MatchPredicate::Pattern(ast) => ast
+            .normalize()
+            .0
+            .iter()
+            .map(|node| node_to_regex_expr(node, wildcard_expr))
+            .collect::<Vec<_>>()
+            .join(""),
+    }
+}
+
+///
+/// Regex expression for a single pattern node.
+///
+pub fn node_to_regex_expr(node: &PatternASTNode, wildcard_expr: &str) -> String {
+    match node {
+        PatternASTNode::Literal(text) => regex_syntax::escape(text),
+        PatternASTNode::Wildcard => wildcard_expr.to_string(),
+        PatternASTNode::NumRange { min, max } => num_range_to_regex_expr(*min, *max),
+        PatternASTNode::Repeat { node, min, max } => format!(
+            "(?:{}){{{},{}}}",
+            node_to_regex_expr(node, wildcard_expr),
+            min,
+            max.map(|max| max.to_string()).unwrap_or_default()
+        ),
+    }
+}
+
+///
+/// Generate a regex alternation matching exactly the integers in `min..=max`.
+///
+/// We compile numeric ranges to plain regex instead of parsing tokens as integers,
+/// so that ranges can be combined with literals and wildcards in the same pattern,
+/// and work the same way in both the regex and the automaton backend.
+///
+/// The range is split into sub-ranges that each can be expressed as a fixed prefix
+/// followed by digit classes, e.g. 10..=20 becomes "1[0-9]|20".
+///
+/// Only canonical decimal integers match: no leading zeros, no "+" sign and no "-0".
+/// A reversed range (min > max) is treated as if its bounds were swapped.
+///
+pub fn num_range_to_regex_expr(min: i64, max: i64) -> String {
+    let (min, max) = if min <= max {
+        (min as i128, max as i128)
+    } else {
+        (max as i128, min as i128)
+    };
+
+    let mut alternatives = vec![];
+
+    if min < 0 {
+        alternatives.push(format!(
+            "-(?:{})",
+            unsigned_range_to_regex_exprs(std::cmp::max(-max, 1), -min).join("|")
+        ));
+    }
+
+    if max >= 0 {
+        alternatives.extend(unsigned_range_to_regex_exprs(std::cmp::max(min, 0), max));
+    }
+
+    format!("(?:{})", alternatives.join("|"))
+}
+
+fn unsigned_range_to_regex_exprs(min: i128, max: i128) -> Vec<String> {
+    let mut stops = BTreeSet::new();
+    stops.insert(max);
+
+    let mut nines = 1;
+    let mut stop = fill_by_nines(min, nines);
+    while min <= stop && stop <= max {
+        stops.insert(stop);
+        nines += 1;
+        stop = fill_by_nines(min, nines);
+    }
+
+    let mut zeros = 1;
+    stop = fill_by_zeros(max + 1, zeros) - 1;
+    while min < stop && stop <= max {
+        stops.insert(stop);
+        zeros += 1;
+        stop = fill_by_zeros(max + 1, zeros) - 1;
+    }
+
+    let mut start = min;
+    let mut exprs = vec![];
+    for stop in stops {
+        exprs.push(digit_range_to_regex_expr(start, stop));
+        start = stop + 1;
+    }
+
+    exprs
+}
+
+/// Replace the last `count` digits of `number` with nines
+fn fill_by_nines(number: i128, count: u32) -> i128 {
+    let power = 10i128.pow(count);
+    number - number % power + power - 1
+}
+
+/// Replace the last `count` digits of `number` with zeros
+fn fill_by_zeros(number: i128, count: u32) -> i128 {
+    number - number % 10i128.pow(count)
+}
+
+///
+/// Expression for a range where `start` and `stop` have the same number of digits,
+/// and differ at most in a prefix digit followed by full 0-9 digits.
+///
+fn digit_range_to_regex_expr(start: i128, stop: i128) -> String {
+    let start_digits = start.to_string();
+    let stop_digits = stop.to_string();
+
+    let mut expr = String::new();
+    let mut any_digit_count = 0;
+
+    for (start_digit, stop_digit) in start_digits.chars().zip(stop_digits.chars()) {
+        if start_digit == stop_digit {
+            expr.push(start_digit);
+        } else if start_digit == '0' && stop_digit == '9' {
+            any_digit_count += 1;
+        } else {
+            expr.push_str(&format!("[{}-{}]", start_digit, stop_digit));
+        }
+    }
+
+    match any_digit_count {
+        0 => {}
+        1 => expr.push_str("[0-9]"),
+        _ => expr.push_str(&format!("[0-9]{{{}}}", any_digit_count)),
+    }
+
+    expr
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches_with_anchor_spec(
+        patterns: &[&[&str]],
+        anchor_spec: AnchorSpec,
+        token_text: &str,
+    ) -> bool {
+        let predicate_set = anchor_spec.apply(&test_util::create_predicate_set(patterns));
+        matches_with_every_backend(&predicate_set, token_text)
+    }
+
+    fn matches_with_every_backend(predicate_set: &MatchPredicateSet, token_text: &str) -> bool {
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(predicate_set);
+
+        let mut regex_matcher = regex_matcher::RegexMatcher::new(
+            regex_matcher::compile_regex(predicate_set).unwrap(),
+            predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+        let mut automaton_matcher = automaton_matcher::AutomatonMatcher::new(
+            automaton_matcher::compile_automaton(predicate_set).unwrap(),
+            predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+        let mut set_matcher = set_matcher::SetMatcher::new(
+            set_matcher::compile_regex_set(predicate_set).unwrap(),
+            predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+
+        let regex_match = test_util::matches(&mut regex_matcher, token_text);
+        let automaton_match = test_util::matches(&mut automaton_matcher, token_text);
+        let set_match = test_util::matches(&mut set_matcher, token_text);
+        assert_eq!(regex_match, automaton_match, "{}", token_text);
+        assert_eq!(regex_match, set_match, "{}", token_text);
+
+        regex_match
+    }
+
+    #[test]
+    fn term_semantics_are_per_term() {
+        let predicate_set: MatchPredicateSet = vec![
+            MatchPredicate::Term("foo".to_string(), TermSemantics::Exact),
+            MatchPredicate::Term("bar".to_string(), TermSemantics::Prefix),
+        ]
+        .into_iter()
+        .collect();
+
+        assert!(matches_with_every_backend(&predicate_set, "foo"));
+        assert!(!matches_with_every_backend(&predicate_set, "foobar"));
+        assert!(matches_with_every_backend(&predicate_set, "bar"));
+        assert!(matches_with_every_backend(&predicate_set, "barfoo"));
+        assert!(!matches_with_every_backend(&predicate_set, "foobarfoo"));
+    }
+
+    #[test]
+    fn literals_with_wildcard_range_boundary_chars_match_next_to_wildcards() {
+        let predicate_set = test_util::create_predicate_set(&[
+            &["\u{0}"],
+            &["\u{24f}"],
+            &["\u{0}x", "*"],
+            &["*", "x\u{24f}"],
+            &["\u{24f}", "*", "\u{0}"],
+        ]);
+        let matches = |token_text| matches_with_every_backend(&predicate_set, token_text);
+
+        assert!(matches("\u{0}"));
+        assert!(matches("\u{24f}"));
+        assert!(!matches("\u{0}\u{0}"));
+        assert!(!matches("\u{250}"));
+
+        assert!(matches("\u{0}x"));
+        assert!(matches("\u{0}x\u{0}\u{24f}"));
+        assert!(!matches("x\u{0}x"));
+
+        assert!(matches("x\u{24f}"));
+        assert!(matches("\u{24f}\u{0}x\u{24f}"));
+        assert!(!matches("x\u{24f}x"));
+
+        assert!(matches("\u{24f}\u{0}"));
+        assert!(matches("\u{24f}-\u{0}"));
+        assert!(matches("\u{24f}\u{24f}\u{0}\u{0}"));
+        assert!(!matches("\u{24f}\u{250}\u{0}"));
+    }
+
+    #[test]
+    fn degenerate_patterns_normalize_to_match_all() {
+        use PatternASTNode::*;
+
+        let empty = || Literal(String::new());
+
+        for nodes in [
+            vec![Wildcard],
+            vec![Wildcard, Wildcard],
+            vec![Wildcard, empty(), Wildcard],
+            vec![empty(), Wildcard, empty()],
+            vec![empty(), Wildcard, Wildcard, empty(), Wildcard],
+        ] {
+            let ast = crate::PatternAST(nodes);
+
+            assert!(ast.is_match_all(), "{:?}", ast);
+            assert_eq!(ast.normalize(), crate::PatternAST(vec![Wildcard]));
+        }
+    }
+
+    #[test]
+    fn normalize_merges_literals_and_collapses_wildcards() {
+        use PatternASTNode::*;
+
+        let ast = crate::PatternAST(vec![
+            Literal("a".to_string()),
+            Literal(String::new()),
+            Literal("b".to_string()),
+            Wildcard,
+            Literal(String::new()),
+            Wildcard,
+            NumRange { min: 1, max: 2 },
+        ]);
+
+        assert!(!ast.is_match_all());
+        assert_eq!(
+            ast.normalize(),
+            crate::PatternAST(vec![
+                Literal("ab".to_string()),
+                Wildcard,
+                NumRange { min: 1, max: 2 }
+            ])
+        );
+        assert_eq!(
+            crate::PatternAST(vec![Literal(String::new())]).normalize(),
+            crate::PatternAST(vec![Literal(String::new())])
+        );
+    }
+
+    #[test]
+    fn match_all_pattern_is_routed_to_match_all() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo"], &["*", "", "*"]]);
+        let groups = GroupedPatterns::group(&predicate_set);
+
+        assert!(groups.match_all);
+        assert_eq!(groups.terms, vec!["foo"]);
+        assert!(groups.wc_terms_wc.is_empty());
+
+        for token_text in &["foo", "bar", ""] {
+            assert!(matches_with_every_backend(&predicate_set, token_text));
+        }
+    }
+
+    #[test]
+    fn repeat_matches_inner_node_between_min_and_max_times() {
+        let repeat = |node: PatternASTNode, min, max| PatternASTNode::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        };
+        let predicate_set: MatchPredicateSet = vec![
+            MatchPredicate::Pattern(crate::PatternAST(vec![repeat(
+                PatternASTNode::Literal("a".to_string()),
+                2,
+                Some(4),
+            )])),
+            MatchPredicate::Pattern(crate::PatternAST(vec![
+                PatternASTNode::Literal("x".to_string()),
+                repeat(PatternASTNode::Literal("yz".to_string()), 1, None),
+                PatternASTNode::Wildcard,
+            ])),
+            MatchPredicate::Pattern(crate::PatternAST(vec![
+                PatternASTNode::Wildcard,
+                repeat(PatternASTNode::NumRange { min: 1, max: 5 }, 3, Some(3)),
+            ])),
+        ]
+        .into_iter()
+        .collect();
+        let matches = |token_text| matches_with_every_backend(&predicate_set, token_text);
+
+        assert!(!matches("a"));
+        assert!(matches("aa"));
+        assert!(matches("aaa"));
+        assert!(matches("aaaa"));
+        assert!(!matches("aaaaa"));
+
+        assert!(matches("xyz"));
+        assert!(matches("xyzyzq"));
+        assert!(!matches("xy"));
+
+        assert!(matches("id-135"));
+        assert!(!matches("id-13"));
+        assert!(!matches("id-136"));
+    }
+
+    #[test]
+    fn required_features_are_detected_in_raw_predicates() {
+        let features = |raw_exprs: &[&str]| {
+            let mut predicate_set = test_util::create_predicate_set(&[&["foo"], &["*", "bar"]]);
+            for expr in raw_exprs {
+                predicate_set.insert(MatchPredicate::Raw(expr.to_string()));
+            }
+            required_features(&predicate_set)
+        };
+
+        assert_eq!(features(&[]), RegexFeatures::default());
+        assert_eq!(features(&["a|b+", "(x)?"]), RegexFeatures::default());
+        assert_eq!(
+            features(&["a|(?:b\\b)+"]),
+            RegexFeatures {
+                anchors: false,
+                word_boundaries: true,
+            }
+        );
+        assert_eq!(
+            features(&["(?m)^a$", "\\Bb"]),
+            RegexFeatures {
+                anchors: true,
+                word_boundaries: true,
+            }
+        );
+    }
+
+    #[test]
+    fn word_boundary_set_is_rejected_by_dfa_backends() {
+        let mut predicate_set = test_util::create_predicate_set(&[&["qux"]]);
+        predicate_set.insert(MatchPredicate::Raw("foo\\b.*".to_string()));
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+
+        assert!(automaton_matcher::compile_automaton(&predicate_set).is_err());
+        assert!(tagged_matcher::compile_tagged_automaton(&predicate_set).is_err());
+
+        let mut regex_matcher = regex_matcher::RegexMatcher::new(
+            regex_matcher::compile_regex(&predicate_set).unwrap(),
+            &predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+        let mut set_matcher = set_matcher::SetMatcher::new(
+            set_matcher::compile_regex_set(&predicate_set).unwrap(),
+            &predicate_set,
+            &term_doc_freq_reciprocals,
+        );
+
+        for (token_text, expected) in &[("foo-bar", true), ("foobar", false), ("qux", true)] {
+            assert_eq!(
+                test_util::matches(&mut regex_matcher, token_text),
+                *expected,
+                "{}",
+                token_text
+            );
+            assert_eq!(
+                test_util::matches(&mut set_matcher, token_text),
+                *expected,
+                "{}",
+                token_text
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "sorted and unique")]
+    fn group_sorted_rejects_unsorted_predicates() {
+        let predicates: Vec<MatchPredicate> = test_util::create_predicate_set(&[&["a"], &["b"]])
+            .into_iter()
+            .rev()
+            .collect();
+
+        GroupedPatterns::group_sorted(&predicates);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "sorted and unique")]
+    fn group_sorted_rejects_duplicate_predicates() {
+        let predicate = MatchPredicate::Term("a".to_string(), TermSemantics::Exact);
+
+        GroupedPatterns::group_sorted(&[predicate.clone(), predicate]);
+    }
+
+    #[test]
+    fn suffix_search_anchors_every_predicate_kind_at_the_end() {
+        let patterns: &[&[&str]] = &[&["com"], &["v", "[1-9]"], &["a", "*", "c"], &["q", "*"]];
+        let suffix_search =
+            |token_text| matches_with_anchor_spec(patterns, AnchorSpec::SuffixSearch, token_text);
+
+        // term
+        assert!(suffix_search("com"));
+        assert!(suffix_search("example.com"));
+        assert!(!suffix_search("example.co"));
+        assert!(!suffix_search("compact"));
+        // pattern ending with a numeric range
+        assert!(suffix_search("rev7"));
+        assert!(!suffix_search("v10"));
+        // pattern with an internal wildcard
+        assert!(suffix_search("zzabbbc"));
+        assert!(!suffix_search("zzabbbcd"));
+        // pattern already ending with a wildcard now matches anywhere
+        assert!(suffix_search("zzqzz"));
+        assert!(!suffix_search("zzz"));
+    }
+
+    #[test]
+    fn prefix_search_anchors_every_predicate_kind_at_the_start() {
+        let patterns: &[&[&str]] = &[&["com"], &["v", "[1-9]"], &["*", "q"]];
+        let prefix_search =
+            |token_text| matches_with_anchor_spec(patterns, AnchorSpec::PrefixSearch, token_text);
+
+        assert!(prefix_search("compact"));
+        assert!(!prefix_search("example.com"));
+        assert!(prefix_search("v7a"));
+        assert!(!prefix_search("rev7"));
+        assert!(prefix_search("zzqzz"));
+    }
+
+    #[test]
+    fn exact_anchor_spec_leaves_predicates_unchanged() {
+        let predicate_set = test_util::create_predicate_set(&[&["com"], &["*", "x"]]);
+
+        assert_eq!(AnchorSpec::Exact.apply(&predicate_set), predicate_set);
+    }
+
+    #[test]
+    fn shared_suffix_regex_expr_shares_suffixes() {
+        assert_eq!(
+            shared_suffix_regex_expr(&["ing", "ring", "bring"]),
+            "(?:(?:b)?r)?ing"
+        );
+        assert_eq!(shared_suffix_regex_expr(&["b", "h"]), "b|h");
+        assert_eq!(
+            shared_suffix_regex_expr(&["sing", "ring", "rang"]),
+            "(?:ra|(?:r|s)i)ng"
+        );
+    }
+
+    #[test]
+    fn shared_prefix_regex_expr_shares_prefixes() {
+        assert_eq!(
+            shared_prefix_regex_expr(&["bar", "baz", "foo", "foob", "fooba"], WILDCARD_MAX_CHAR),
+            "ba(?:r|z)|foo"
+        );
+        assert_eq!(
+            shared_prefix_regex_expr(&["fooba", "foo"], WILDCARD_MAX_CHAR),
+            "foo"
+        );
+        assert_eq!(
+            shared_prefix_regex_expr(&["a*", "a+"], WILDCARD_MAX_CHAR),
+            "a(?:\\*|\\+)"
+        );
+        assert_eq!(
+            shared_prefix_regex_expr(&["foo", ""], WILDCARD_MAX_CHAR),
+            ""
+        );
+        assert_eq!(
+            shared_prefix_regex_expr(&["foo", "foo😀", "fooba"], WILDCARD_MAX_CHAR),
+            "foo(?:😀)?"
+        );
+        assert_eq!(
+            shared_prefix_regex_expr(&["foo", "fooé"], ASCII_WILDCARD_MAX_CHAR),
+            "foo(?:é)?"
+        );
+        assert_eq!(
+            shared_prefix_regex_expr(&["", "😀"], WILDCARD_MAX_CHAR),
+            "(?:😀)?"
+        );
+    }
+
+    #[test]
+    fn char_class_regex_expr_uses_ranges_and_escapes() {
+        assert_eq!(char_class_regex_expr(&['x', 'b', 'a', 'c', 'a']), "[a-cx]");
+        assert_eq!(char_class_regex_expr(&['-', '^', '[']), "[\\-\\[\\^]");
+    }
+
+    #[test]
+    fn split_wide_terms_only_splits_wide_alternations() {
+        let (alternatives, chars) = split_wide_terms(&["a", "bc"]);
+        assert_eq!(alternatives, vec!["a", "bc"]);
+        assert!(chars.is_empty());
+
+        let terms: Vec<String> = (0..=WIDE_ALTERNATION_THRESHOLD)
+            .map(|i| std::char::from_u32(0x4e00 + i as u32).unwrap().to_string())
+            .chain(std::iter::once("bc".to_string()))
+            .collect();
+        let terms: Vec<&str> = terms.iter().map(|term| term.as_str()).collect();
+        let (alternatives, chars) = split_wide_terms(&terms);
+        assert_eq!(alternatives, vec!["bc"]);
+        assert_eq!(chars.len(), WIDE_ALTERNATION_THRESHOLD + 1);
+    }
+
+    #[test]
+    fn reject_empty_matching_rejects_predicates_matching_the_empty_string() {
+        let options = CompileOptions {
+            reject_empty_matching: true,
+            ..CompileOptions::default()
+        };
+        let check =
+            |patterns: &[&[&str]]| options.check(&test_util::create_predicate_set(patterns));
+
+        assert!(check(&[&["*"]]).is_err());
+        assert!(check(&[&["foo"], &["", "*", ""]]).is_err());
+        assert!(check(&[&["foo", "*"]]).is_ok());
+        assert!(check(&[&["*", "[0-9]"]]).is_ok());
+
+        let mut predicate_set = test_util::create_predicate_set(&[&["foo", "*"]]);
+        predicate_set.insert(MatchPredicate::Pattern(crate::PatternAST(vec![
+            PatternASTNode::Repeat {
+                node: Box::new(PatternASTNode::Literal("ab".to_string())),
+                min: 0,
+                max: Some(2),
+            },
+        ])));
+        assert!(options.check(&predicate_set).is_err());
+
+        let mut predicate_set = test_util::create_predicate_set(&[&["foo", "*"]]);
+        predicate_set.insert(MatchPredicate::Raw("x?".to_string()));
+        assert!(options.check(&predicate_set).is_err());
+
+        assert!(CompileOptions::default()
+            .check(&test_util::create_predicate_set(&[&["*"]]))
+            .is_ok());
+    }
+
+    #[test]
+    fn lazy_wildcards_match_shorter_spans_than_greedy_ones() {
+        let predicate_set = test_util::create_predicate_set(&[&["a", "*", "b"]]);
+        let match_predicate = predicate_set.iter().next().unwrap();
+        let span = |quantifier: WildcardQuantifier| {
+            let expr = predicate_to_regex_expr(match_predicate, &quantifier.wildcard_expr(true));
+            regex::Regex::new(&expr)
+                .unwrap()
+                .find("xaxbxbx")
+                .unwrap()
+                .range()
+        };
+
+        assert_eq!(span(WildcardQuantifier::Greedy), 1..6);
+        assert_eq!(span(WildcardQuantifier::Lazy), 1..4);
+    }
+
+    #[test]
+    fn profile_counts_groups_and_literals() {
+        let mut predicate_set = test_util::create_predicate_set(&[
+            &["foo"],
+            &["bar"],
+            &["ab", "*"],
+            &["a", "*", "b"],
+            &["[1-9]"],
+            &["*", "xyz"],
+            &["*", "q", "*"],
+            &["*"],
+        ]);
+        predicate_set.insert(MatchPredicate::Term(
+            "pre".to_string(),
+            TermSemantics::Prefix,
+        ));
+        predicate_set.insert(MatchPredicate::Raw("x+".to_string()));
+
+        assert_eq!(
+            profile(&predicate_set),
+            PredicateProfile {
+                terms: 2,
+                prefix_terms: 1,
+                terms_wc: 1,
+                terms_internal_wc: 2,
+                wc_terms: 1,
+                wc_terms_wc: 1,
+                raw: 1,
+                match_all: true,
+                literal_bytes: 3 + 3 + 2 + 2 + 3 + 1 + 3,
+                max_alternation_width: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn num_range_to_regex_expr_splits_into_prefixed_digit_ranges() {
+        assert_eq!(num_range_to_regex_expr(10, 20), "(?:1[0-9]|20)");
+        assert_eq!(num_range_to_regex_expr(0, 9), "(?:[0-9])");
+        assert_eq!(num_range_to_regex_expr(1, 100), "(?:[1-9]|[1-9][0-9]|100)");
+        assert_eq!(num_range_to_regex_expr(-5, 5), "(?:-(?:[1-5])|[0-5])");
+    }
+
+    #[test]
+    fn num_range_to_regex_expr_matches_exactly_the_range() {
+        for (min, max) in [
+            (0, 0),
+            (7, 7),
+            (10, 20),
+            (5, 25),
+            (-25, 130),
+            (-130, -25),
+            (99, 1000),
+        ] {
+            let regex =
+                regex::Regex::new(&format!("^{}$", num_range_to_regex_expr(min, max))).unwrap();
+
+            for number in -1500..1500 {
+                assert_eq!(
+                    regex.is_match(&number.to_string()),
+                    min <= number && number <= max,
+                    "{} in {}..={}",
+                    number,
+                    min,
+                    max
+                );
+            }
+
+            assert!(!regex.is_match("-0"));
+            assert!(!regex.is_match(&format!("0{}", max)));
+        }
+    }
+
+    #[test]
+    fn num_range_to_regex_expr_handles_extreme_bounds() {
+        let regex = regex::Regex::new(&format!(
+            "^{}$",
+            num_range_to_regex_expr(i64::MIN, i64::MAX)
+        ))
+        .unwrap();
+
+        assert!(regex.is_match(&i64::MIN.to_string()));
+        assert!(regex.is_match(&i64::MAX.to_string()));
+        assert!(regex.is_match("0"));
+        assert!(!regex.is_match("99999999999999999999"));
     }
 }
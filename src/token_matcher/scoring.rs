@@ -0,0 +1,253 @@
+use std::ops::Range;
+
+use super::*;
+
+///
+/// Score a document from the positions of its matching tokens,
+/// rewarding matches that appear close together.
+///
+/// `matches` is a list of (token position, doc freq reciprocal), in any order.
+///
+/// The base score is the sum of the reciprocals of all matches.
+/// On top of that, every pair of consecutive matches less than `window` positions apart
+/// adds a proximity bonus: the sum of the pair's reciprocals, scaled by closeness.
+/// Adjacent tokens get the full bonus, falling linearly to nothing at `window` positions apart.
+///
+pub fn proximity_score(matches: &[(usize, DocFreqReciprocal)], window: usize) -> f32 {
+    let mut sorted_matches: Vec<&(usize, DocFreqReciprocal)> = matches.iter().collect();
+    sorted_matches.sort_by_key(|(position, _)| *position);
+
+    let base_score: f32 = sorted_matches.iter().map(|(_, dfr)| dfr.0).sum();
+
+    if window <= 1 {
+        return base_score;
+    }
+
+    let proximity_bonus: f32 = sorted_matches
+        .windows(2)
+        .map(|pair| {
+            let (first_position, first_dfr) = pair[0];
+            let (second_position, second_dfr) = pair[1];
+            let distance = second_position - first_position;
+
+            if distance >= window {
+                0.0
+            } else {
+                let closeness = ((window - distance) as f32 / (window - 1) as f32).min(1.0);
+                (first_dfr.0 + second_dfr.0) * closeness
+            }
+        })
+        .sum();
+
+    base_score + proximity_bonus
+}
+
+///
+/// Normalize a batch of document scores to [0, 1], relative to the best scoring document.
+///
+/// The best document gets 1.0, and a batch where nothing scored stays all zeros.
+///
+pub fn normalize_document_scores(scores: &[f32]) -> Vec<f32> {
+    let max_score = scores.iter().cloned().fold(0.0, f32::max);
+
+    if max_score > 0.0 {
+        scores.iter().map(|score| score / max_score).collect()
+    } else {
+        vec![0.0; scores.len()]
+    }
+}
+
+///
+/// A fragment of a document, i.e. a window of consecutive tokens, scored for use as a snippet.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoredFragment {
+    /// The positions of the fragment's tokens
+    pub tokens: Range<usize>,
+    /// The text covered, from the start of the first token to the end of the last token
+    pub range: Range<usize>,
+    /// The `proximity_score` of the matches in the fragment
+    pub score: f32,
+}
+
+///
+/// The `k` highest scoring, non-overlapping fragments of `window` tokens, best first.
+///
+/// `token_ranges` are the text ranges of all tokens of a document, in order,
+/// and `matches` the (token position, doc freq reciprocal) of its matching tokens.
+///
+/// A candidate fragment starts at a matching token (or ends with the document's last token),
+/// so a snippet begins at a match rather than with unrelated text.
+/// Fragments without matches are never returned.
+///
+pub fn rank_fragments(
+    token_ranges: &[Range<usize>],
+    matches: &[(usize, DocFreqReciprocal)],
+    window: usize,
+    k: usize,
+) -> Vec<ScoredFragment> {
+    if token_ranges.is_empty() || window == 0 {
+        return vec![];
+    }
+
+    let mut sorted_matches: Vec<(usize, DocFreqReciprocal)> = matches.to_vec();
+    sorted_matches.sort_by_key(|(position, _)| *position);
+
+    // Candidates start in position order, so the matches inside them form a sliding window
+    // of the sorted matches, from `first_match` up to (not including) `end_match`:
+    let mut first_match = 0;
+    let mut end_match = 0;
+
+    let mut candidates: Vec<ScoredFragment> = Vec::with_capacity(sorted_matches.len());
+    for (position, _) in &sorted_matches {
+        let start = (*position).min(token_ranges.len().saturating_sub(window));
+        let end = (start + window).min(token_ranges.len());
+
+        while sorted_matches[first_match].0 < start {
+            first_match += 1;
+        }
+        end_match = end_match.max(first_match);
+        while end_match < sorted_matches.len() && sorted_matches[end_match].0 < end {
+            end_match += 1;
+        }
+
+        candidates.push(ScoredFragment {
+            tokens: start..end,
+            range: token_ranges[start].start..token_ranges[end - 1].end,
+            score: proximity_score(&sorted_matches[first_match..end_match], window),
+        });
+    }
+
+    // The best first, and of equally good fragments the earliest:
+    candidates.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.tokens.start.cmp(&b.tokens.start))
+    });
+
+    let mut fragments: Vec<ScoredFragment> = vec![];
+    for candidate in candidates {
+        if fragments.len() == k {
+            break;
+        }
+        let overlaps = fragments.iter().any(|fragment| {
+            candidate.tokens.start < fragment.tokens.end
+                && fragment.tokens.start < candidate.tokens.end
+        });
+        if !overlaps {
+            fragments.push(candidate);
+        }
+    }
+
+    fragments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dfr(doc_freq: u64) -> DocFreqReciprocal {
+        DocFreqReciprocal::from_doc_freq(doc_freq).unwrap()
+    }
+
+    #[test]
+    fn proximity_score_is_higher_for_closer_matches() {
+        let adjacent = proximity_score(&[(3, dfr(1)), (4, dfr(2))], 5);
+        let near = proximity_score(&[(3, dfr(1)), (6, dfr(2))], 5);
+        let far = proximity_score(&[(3, dfr(1)), (30, dfr(2))], 5);
+
+        assert!(adjacent > near);
+        assert!(near > far);
+        assert_eq!(far, dfr(1).0 + dfr(2).0);
+        assert_eq!(adjacent, 2.0 * (dfr(1).0 + dfr(2).0));
+    }
+
+    #[test]
+    fn normalized_score_maps_doc_freq_1_to_1() {
+        assert_eq!(dfr(1).normalized_score(), 1.0);
+        assert!((dfr(2).normalized_score() - 2.0 / 3.0).abs() < f32::EPSILON);
+        assert!(dfr(1000).normalized_score() > 0.0);
+    }
+
+    #[test]
+    fn normalize_document_scores_is_relative_to_best_document() {
+        assert_eq!(
+            normalize_document_scores(&[0.5, 2.0, 0.0, 1.0]),
+            vec![0.25, 1.0, 0.0, 0.5]
+        );
+        assert_eq!(normalize_document_scores(&[0.0, 0.0]), vec![0.0, 0.0]);
+        assert!(normalize_document_scores(&[]).is_empty());
+    }
+
+    #[test]
+    fn rank_fragments_picks_the_best_non_overlapping_windows() {
+        let token_ranges: Vec<Range<usize>> = (0..20).map(|i| i * 2..i * 2 + 1).collect();
+        let matches = [
+            (2, dfr(1)),
+            (3, dfr(1)),
+            (4, dfr(5)),
+            (11, dfr(1)),
+            (18, dfr(9)),
+        ];
+
+        let fragments = rank_fragments(&token_ranges, &matches, 4, 5);
+
+        let tokens: Vec<Range<usize>> = fragments.iter().map(|f| f.tokens.clone()).collect();
+        assert_eq!(tokens, vec![2..6, 11..15, 16..20]);
+        assert_eq!(fragments[0].range, 4..11);
+        assert!(fragments[0].score > fragments[1].score);
+        assert!(fragments[1].score > fragments[2].score);
+
+        assert_eq!(rank_fragments(&token_ranges, &matches, 4, 1).len(), 1);
+
+        let mut shuffled = matches.to_vec();
+        shuffled.reverse();
+        assert_eq!(rank_fragments(&token_ranges, &shuffled, 4, 5), fragments);
+        assert!(rank_fragments(&token_ranges, &[], 4, 3).is_empty());
+    }
+
+    #[test]
+    fn top_fragments_ranks_snippets_of_a_paragraph() {
+        let paragraph = "The regex crate compiles patterns lazily. \
+            Unrelated text follows here, about nothing in particular at all. \
+            A dense automaton matches patterns fast, and the regex matcher too.";
+        let tokens: Vec<(&str, Range<usize>)> = paragraph
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                let start = token.as_ptr() as usize - paragraph.as_ptr() as usize;
+                (token, start..start + token.len())
+            })
+            .collect();
+
+        let mut matcher = regex_matcher::test::test_regex_matcher(&[
+            &["regex"],
+            &["pattern", "*"],
+            &["automaton"],
+        ]);
+
+        let fragments = matcher.top_fragments(&tokens, 5, 2, &test_util::AnyTermDb);
+        let snippets: Vec<&str> = fragments
+            .iter()
+            .map(|fragment| &paragraph[fragment.range.clone()])
+            .collect();
+
+        assert_eq!(
+            snippets,
+            vec![
+                "automaton matches patterns fast, and",
+                "regex crate compiles patterns lazily"
+            ]
+        );
+        assert!(fragments[0].score > fragments[1].score);
+    }
+
+    #[test]
+    fn proximity_score_does_not_depend_on_match_order() {
+        let matches = [(10, dfr(1)), (2, dfr(3)), (4, dfr(2))];
+        let reversed = [(4, dfr(2)), (2, dfr(3)), (10, dfr(1))];
+
+        assert_eq!(proximity_score(&matches, 4), proximity_score(&reversed, 4));
+    }
+}
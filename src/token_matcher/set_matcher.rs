@@ -0,0 +1,885 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use fixedbitset::FixedBitSet;
+
+use crate::PatternASTNode;
+
+use super::regex_util::{
+    node_to_regex_expr, predicate_to_regex_expr, wildcard_count, CompileOptions, WILDCARD_EXPR,
+};
+use super::*;
+
+///
+/// Matcher backed by a `regex::RegexSet` with one pattern per predicate.
+///
+/// Compared to the `RegexMatcher`, this knows about _every_ predicate matching a token,
+/// not just the first alternative of one big regex.
+/// The price is a larger compiled structure and somewhat slower matching.
+///
+/// Because predicate boundaries are preserved, attribution is exact:
+/// each pattern index of the set maps to exactly one predicate,
+/// there is no need to guess from capture groups of a combined regex.
+///
+pub struct SetMatcher {
+    regex_set: regex::RegexSet,
+
+    // The predicates, in the same order as the patterns of the regex set
+    predicates: Vec<MatchPredicate>,
+    // Per predicate regexes capturing its span, compiled on demand by `all_match_spans`
+    span_regexes: Vec<Option<regex::Regex>>,
+    // Per predicate regexes capturing each literal, compiled on demand by `matched_literal_spans`
+    literal_span_regexes: Vec<Option<regex::Regex>>,
+
+    term_doc_freq_reciprocals_map: HashMap<String, DocFreqReciprocal>,
+    pattern_doc_freq_cache: HashMap<String, Option<DocFreqReciprocal>>,
+
+    default_reciprocal: Option<DocFreqReciprocal>,
+
+    metrics: Metrics,
+}
+
+impl SetMatcher {
+    pub fn new(
+        regex_set: regex::RegexSet,
+        predicate_set: &MatchPredicateSet,
+        term_doc_freq_reciprocals_map: &HashMap<String, DocFreqReciprocal>,
+    ) -> Self {
+        Self {
+            regex_set,
+            predicates: predicate_set.iter().cloned().collect(),
+            span_regexes: vec![None; predicate_set.len()],
+            literal_span_regexes: vec![None; predicate_set.len()],
+            term_doc_freq_reciprocals_map: term_doc_freq_reciprocals_map.clone(),
+            pattern_doc_freq_cache: HashMap::new(),
+            default_reciprocal: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    ///
+    /// Set the reciprocal of matched tokens with an unknown doc freq,
+    /// including term predicates missing from the doc freq map,
+    /// see `RegexMatcher::set_default_reciprocal`.
+    ///
+    pub fn set_default_reciprocal(&mut self, default_reciprocal: Option<DocFreqReciprocal>) {
+        self.default_reciprocal = default_reciprocal;
+    }
+
+    ///
+    /// Forget the cached doc freq of a token, see `RegexMatcher::invalidate_term`.
+    /// Term predicates are not affected.
+    ///
+    pub fn invalidate_term(&mut self, term_text: &str) {
+        self.pattern_doc_freq_cache.remove(term_text);
+    }
+
+    ///
+    /// Inherit the doc freqs cached by another set matcher, see `RegexMatcher::transfer_cache`.
+    ///
+    pub fn transfer_cache(&mut self, from: &Self) {
+        for (token_text, doc_freq_reciprocal) in &from.pattern_doc_freq_cache {
+            self.pattern_doc_freq_cache
+                .entry(token_text.clone())
+                .or_insert_with(|| doc_freq_reciprocal.clone());
+        }
+    }
+
+    ///
+    /// Whether each token matches, one bit per token,
+    /// for callers not interested in doc freqs: this never calls `GetDocFreq`.
+    ///
+    pub fn match_mask(&mut self, tokens: &[&str]) -> FixedBitSet {
+        let mut mask = FixedBitSet::with_capacity(tokens.len());
+
+        for (index, token_text) in tokens.iter().enumerate() {
+            self.metrics.lookups += 1;
+            if self.regex_set.is_match(token_text) {
+                self.metrics.matches += 1;
+                mask.insert(index);
+            }
+        }
+
+        mask
+    }
+
+    ///
+    /// The number of predicates matching the token.
+    ///
+    pub fn match_count(&self, token_text: &str) -> usize {
+        self.regex_set.matches(token_text).iter().count()
+    }
+
+    ///
+    /// All predicates matching the token, in predicate set order.
+    ///
+    pub fn matching_predicates(&self, token_text: &str) -> Vec<PredicateRef<'_>> {
+        self.regex_set
+            .matches(token_text)
+            .iter()
+            .map(|index| self.predicate_ref(index))
+            .collect()
+    }
+
+    ///
+    /// All predicates matching the token, the most specific first,
+    /// e.g. for showing the most meaningful match first in a UI:
+    ///
+    /// 1. exact terms
+    /// 2. more literal characters
+    /// 3. fewer wildcards
+    ///
+    /// Equally specific predicates are in predicate set order.
+    ///
+    pub fn matches_by_specificity(&self, token_text: &str) -> Vec<PredicateRef<'_>> {
+        let mut predicate_refs = self.matching_predicates(token_text);
+        predicate_refs.sort_by_key(|predicate_ref| specificity_key(predicate_ref.predicate));
+        predicate_refs
+    }
+
+    ///
+    /// All predicates matching the token, in predicate set order, each with its span in the token,
+    /// e.g. for highlighting every match of the token.
+    ///
+    /// Every predicate matches the whole token, so the span is the part of the token
+    /// between the leading and trailing wildcards of a pattern (or after a prefix term),
+    /// i.e. from the first to the last character the predicate constrains.
+    /// Leading wildcards match as little as possible, so the span starts as early as it can.
+    ///
+    /// The span regex of a predicate is compiled the first time the predicate matches.
+    /// The DFA backends (automaton and tagged) don't offer this, not even as end offsets:
+    /// a DFA only knows where a match ends, and with every predicate matching the whole token,
+    /// that is always the end of the token.
+    ///
+    pub fn all_match_spans(&mut self, token_text: &str) -> Vec<(PredicateRef<'_>, Range<usize>)> {
+        let predicate_indexes: Vec<usize> = self.regex_set.matches(token_text).iter().collect();
+
+        let mut spans = Vec::with_capacity(predicate_indexes.len());
+        for predicate_index in predicate_indexes {
+            let predicates = &self.predicates;
+            let span_regex = self.span_regexes[predicate_index].get_or_insert_with(|| {
+                regex::Regex::new(&span_regex_expr(&predicates[predicate_index]))
+                    .expect("the predicate compiled in the regex set")
+            });
+            let span = span_regex
+                .captures(token_text)
+                .and_then(|captures| captures.get(1))
+                .map_or(0..0, |span_match| span_match.range());
+
+            spans.push((predicate_index, span));
+        }
+
+        let matcher = &*self;
+        spans
+            .into_iter()
+            .map(move |(predicate_index, span)| (matcher.predicate_ref(predicate_index), span))
+            .collect()
+    }
+
+    ///
+    /// Scan the tokens of a document, calling `on_match` with (predicate, token index, score)
+    /// for every predicate matching every token, without collecting the matches.
+    ///
+    /// Scores are those of `lookup_best_score` for each single predicate:
+    /// term predicates are scored by their precomputed reciprocal,
+    /// pattern predicates by the doc freq of the token.
+    /// Matches without a score (e.g. a doc freq of 0) are skipped.
+    ///
+    pub fn scan(
+        &mut self,
+        tokens: &[&str],
+        get_doc_freq: &impl GetDocFreq,
+        mut on_match: impl FnMut(PredicateRef<'_>, usize, DocFreqReciprocal),
+    ) {
+        for (token_index, token_text) in tokens.iter().enumerate() {
+            self.metrics.lookups += 1;
+
+            let set_matches = self.regex_set.matches(token_text);
+            if !set_matches.matched_any() {
+                continue;
+            }
+
+            self.metrics.matches += 1;
+
+            for predicate_index in set_matches.iter() {
+                let score = match &self.predicates[predicate_index] {
+                    MatchPredicate::Term(term_text, TermSemantics::Exact) => {
+                        self.metrics.cache_hits += 1;
+                        self.term_doc_freq_reciprocals_map.get(term_text).cloned()
+                    }
+                    MatchPredicate::Term(_, TermSemantics::Prefix)
+                    | MatchPredicate::Pattern(_)
+                    | MatchPredicate::Raw(_) => {
+                        self.pattern_doc_freq_reciprocal(token_text, get_doc_freq)
+                    }
+                };
+
+                if let Some(score) = score {
+                    on_match(self.predicate_ref(predicate_index), token_index, score);
+                }
+            }
+        }
+    }
+
+    ///
+    /// The spans of the literal parts of the first predicate (in predicate set order)
+    /// matching the token, e.g. for highlighting only the literals of a wildcard match:
+    /// `foo*bar` matching `fooXYZbar` gives the spans of `foo` and `bar`.
+    ///
+    /// A term is one literal, numeric ranges and repeats are not literals,
+    /// and nothing is known about the literals of a raw expression.
+    /// `None` if no predicate matches the token.
+    ///
+    pub fn matched_literal_spans(&mut self, token_text: &str) -> Option<Vec<Range<usize>>> {
+        let predicate_index = self.regex_set.matches(token_text).iter().next()?;
+
+        if let MatchPredicate::Raw(_) = &self.predicates[predicate_index] {
+            return Some(vec![]);
+        }
+
+        let predicates = &self.predicates;
+        let literal_span_regex =
+            self.literal_span_regexes[predicate_index].get_or_insert_with(|| {
+                regex::Regex::new(&literal_span_regex_expr(&predicates[predicate_index]))
+                    .expect("the predicate compiled in the regex set")
+            });
+
+        let captures = literal_span_regex.captures(token_text)?;
+
+        Some(
+            captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .map(|literal_match| literal_match.range())
+                .collect(),
+        )
+    }
+
+    ///
+    /// The first predicate (in predicate set order) matching the token.
+    ///
+    pub fn attribute(&self, token_text: &str) -> Option<PredicateRef<'_>> {
+        self.regex_set
+            .matches(token_text)
+            .iter()
+            .next()
+            .map(|index| self.predicate_ref(index))
+    }
+
+    ///
+    /// The highest doc freq reciprocal, i.e. the score of the rarest predicate,
+    /// among all predicates matching the token.
+    ///
+    /// Term predicates are scored by their precomputed reciprocal,
+    /// pattern predicates by the doc freq of the token itself.
+    ///
+    pub fn lookup_best_score(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        self.metrics.lookups += 1;
+
+        let set_matches = self.regex_set.matches(token_text);
+        if !set_matches.matched_any() {
+            return None;
+        }
+
+        self.metrics.matches += 1;
+
+        let mut best_score = None;
+        let mut pattern_matched = false;
+
+        for predicate_index in set_matches.iter() {
+            match &self.predicates[predicate_index] {
+                MatchPredicate::Term(term_text, TermSemantics::Exact) => {
+                    best_score = max_score(best_score, self.term_doc_freq_reciprocal(term_text));
+                }
+                MatchPredicate::Term(_, TermSemantics::Prefix)
+                | MatchPredicate::Pattern(_)
+                | MatchPredicate::Raw(_) => {
+                    pattern_matched = true;
+                }
+            }
+        }
+
+        if pattern_matched {
+            let pattern_score = self.pattern_doc_freq_reciprocal(token_text, get_doc_freq);
+            best_score = max_score(best_score, pattern_score);
+        } else {
+            self.metrics.cache_hits += 1;
+        }
+
+        best_score
+    }
+
+    ///
+    /// The precomputed reciprocal of a term predicate,
+    /// or the default reciprocal for a term missing from the doc freq map.
+    ///
+    fn term_doc_freq_reciprocal(&self, term_text: &str) -> Option<DocFreqReciprocal> {
+        self.term_doc_freq_reciprocals_map
+            .get(term_text)
+            .cloned()
+            .or_else(|| self.default_reciprocal.clone())
+    }
+
+    fn pattern_doc_freq_reciprocal(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        if let Some(pattern_doc_freq) = self.pattern_doc_freq_cache.get(token_text) {
+            self.metrics.cache_hits += 1;
+            return pattern_doc_freq.clone();
+        }
+
+        self.metrics.cache_misses += 1;
+        self.metrics.get_doc_freq_calls += 1;
+
+        let doc_freq_reciprocal = DocFreqReciprocal::from_try_doc_freq(
+            get_doc_freq.try_get_doc_freq(crate::TermRef::from(token_text)),
+            &self.default_reciprocal,
+        );
+
+        self.pattern_doc_freq_cache
+            .insert(token_text.to_string(), doc_freq_reciprocal.clone());
+
+        doc_freq_reciprocal
+    }
+
+    fn predicate_ref(&self, index: usize) -> PredicateRef<'_> {
+        PredicateRef {
+            index,
+            predicate: &self.predicates[index],
+        }
+    }
+}
+
+impl LookupDocFreqReciprocal for SetMatcher {
+    fn lookup_doc_freq_reciprocal(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        self.metrics.lookups += 1;
+
+        let predicate_index = self.regex_set.matches(token_text).iter().next()?;
+
+        self.metrics.matches += 1;
+
+        if let MatchPredicate::Term(term_text, TermSemantics::Exact) =
+            &self.predicates[predicate_index]
+        {
+            self.metrics.cache_hits += 1;
+            return self.term_doc_freq_reciprocal(term_text);
+        }
+
+        self.pattern_doc_freq_reciprocal(token_text, get_doc_freq)
+    }
+}
+
+///
+/// Sort key ordering the most specific predicates first, see `SetMatcher::matches_by_specificity`.
+///
+fn specificity_key(match_predicate: &MatchPredicate) -> (bool, Reverse<usize>, usize) {
+    match match_predicate {
+        MatchPredicate::Term(term_text, TermSemantics::Exact) => {
+            (false, Reverse(term_text.chars().count()), 0)
+        }
+        MatchPredicate::Term(term_text, TermSemantics::Prefix) => {
+            (true, Reverse(term_text.chars().count()), 1)
+        }
+        MatchPredicate::Pattern(ast) => (
+            true,
+            Reverse(ast.0.iter().map(literal_char_count).sum()),
+            ast.0.iter().map(wildcard_count).sum(),
+        ),
+        // Nothing is known about raw expressions:
+        MatchPredicate::Raw(_) => (true, Reverse(0), usize::MAX),
+    }
+}
+
+///
+/// Whole-token regex of a predicate, capturing its span (see `SetMatcher::all_match_spans`) as group 1.
+///
+fn span_regex_expr(match_predicate: &MatchPredicate) -> String {
+    let (leading_wildcard, span_expr, trailing_wildcard) = match match_predicate {
+        MatchPredicate::Term(term_text, TermSemantics::Prefix) => {
+            (false, regex_syntax::escape(term_text), true)
+        }
+        MatchPredicate::Pattern(ast) => {
+            let ast = ast.normalize();
+            let mut nodes = ast.0.as_slice();
+
+            let leading_wildcard = nodes.first() == Some(&PatternASTNode::Wildcard);
+            if leading_wildcard {
+                nodes = &nodes[1..];
+            }
+            let trailing_wildcard = nodes.last() == Some(&PatternASTNode::Wildcard);
+            if trailing_wildcard {
+                nodes = &nodes[..nodes.len() - 1];
+            }
+
+            let span_expr = nodes
+                .iter()
+                .map(|node| node_to_regex_expr(node, WILDCARD_EXPR))
+                .collect::<Vec<_>>()
+                .join("");
+
+            (leading_wildcard, span_expr, trailing_wildcard)
+        }
+        MatchPredicate::Term(_, TermSemantics::Exact) | MatchPredicate::Raw(_) => (
+            false,
+            predicate_to_regex_expr(match_predicate, WILDCARD_EXPR),
+            false,
+        ),
+    };
+
+    format!(
+        "^{}({}){}$",
+        if leading_wildcard {
+            format!("{}?", WILDCARD_EXPR)
+        } else {
+            String::new()
+        },
+        span_expr,
+        if trailing_wildcard { WILDCARD_EXPR } else { "" }
+    )
+}
+
+///
+/// Whole-token regex of a predicate with one capture group per literal,
+/// see `SetMatcher::matched_literal_spans`.
+///
+fn literal_span_regex_expr(match_predicate: &MatchPredicate) -> String {
+    let expr = match match_predicate {
+        MatchPredicate::Term(term_text, TermSemantics::Exact) => {
+            format!("({})", regex_syntax::escape(term_text))
+        }
+        MatchPredicate::Term(term_text, TermSemantics::Prefix) => {
+            format!("({}){}", regex_syntax::escape(term_text), WILDCARD_EXPR)
+        }
+        MatchPredicate::Pattern(ast) => ast
+            .normalize()
+            .0
+            .iter()
+            .map(|node| match node {
+                PatternASTNode::Literal(text) if !text.is_empty() => {
+                    format!("({})", regex_syntax::escape(text))
+                }
+                _ => node_to_regex_expr(node, WILDCARD_EXPR),
+            })
+            .collect::<Vec<_>>()
+            .join(""),
+        MatchPredicate::Raw(_) => unreachable!("raw expressions have no known literals"),
+    };
+
+    format!("^{}$", expr)
+}
+
+fn literal_char_count(node: &PatternASTNode) -> usize {
+    match node {
+        PatternASTNode::Literal(text) => text.chars().count(),
+        PatternASTNode::Repeat { node, min, .. } => literal_char_count(node) * min,
+        PatternASTNode::Wildcard | PatternASTNode::NumRange { .. } => 0,
+    }
+}
+
+fn max_score(
+    a: Option<DocFreqReciprocal>,
+    b: Option<DocFreqReciprocal>,
+) -> Option<DocFreqReciprocal> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.0 > a.0 { b } else { a }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+///
+/// Compile a regex set with one whole-token pattern per predicate, in predicate set order.
+///
+pub fn compile_regex_set(predicate_set: &MatchPredicateSet) -> Result<regex::RegexSet, String> {
+    regex::RegexSet::new(predicate_set.iter().map(|match_predicate| {
+        format!(
+            "^(?:{})$",
+            predicate_to_regex_expr(match_predicate, WILDCARD_EXPR)
+        )
+    }))
+    .map_err(|error| format!("compile_regex_set failed. {:?}", error))
+}
+
+///
+/// Compile a regex set, first checking the predicate set against the limits of the options.
+///
+pub fn compile_regex_set_with_options(
+    predicate_set: &MatchPredicateSet,
+    options: &CompileOptions,
+) -> Result<regex::RegexSet, String> {
+    options
+        .check(predicate_set)
+        .map_err(|error| format!("compile_regex_set: {}", error))?;
+
+    compile_regex_set(predicate_set)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub fn test_set_matcher(patterns: &[&[&str]]) -> SetMatcher {
+        let predicate_set = test_util::create_predicate_set(patterns);
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+
+        SetMatcher::new(
+            compile_regex_set(&predicate_set).unwrap(),
+            &predicate_set,
+            &term_doc_freq_reciprocals,
+        )
+    }
+
+    #[test]
+    fn all_match_spans_reports_the_span_of_every_matching_predicate() {
+        let mut matcher = test_set_matcher(&[
+            &["*", "b", "*", "c", "*"],
+            &["*", "c", "*", "d", "*"],
+            &["a", "*", "e"],
+            &["abcde"],
+            &["ab", "*"],
+            &["*", "x", "*"],
+        ]);
+
+        let spans: Vec<(MatchPredicate, Range<usize>)> = matcher
+            .all_match_spans("abcde")
+            .into_iter()
+            .map(|(predicate_ref, span)| (predicate_ref.predicate.clone(), span))
+            .collect();
+
+        let predicate = |pattern: &[&str]| {
+            test_util::create_predicate_set(&[pattern])
+                .into_iter()
+                .next()
+                .unwrap()
+        };
+        let mut expected = vec![
+            (predicate(&["*", "b", "*", "c", "*"]), 1..3),
+            (predicate(&["*", "c", "*", "d", "*"]), 2..4),
+            (predicate(&["a", "*", "e"]), 0..5),
+            (predicate(&["abcde"]), 0..5),
+            (predicate(&["ab", "*"]), 0..2),
+        ];
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(spans, expected);
+        assert_eq!(matcher.all_match_spans("xyz").len(), 1);
+        assert!(matcher.all_match_spans("qqq").is_empty());
+    }
+
+    #[test]
+    fn scan_calls_back_for_every_matching_predicate_and_token() {
+        let mut matcher = test_set_matcher(&[&["foo"], &["fo", "*"], &["*", "bar"]]);
+        let tokens = ["foo", "qux", "bar", "foobar", "fo"];
+
+        let mut scanned: Vec<(usize, usize)> = vec![];
+        matcher.scan(
+            &tokens,
+            &test_util::AnyTermDb,
+            |predicate_ref, token_index, score| {
+                assert!(score.0 > 0.0);
+                scanned.push((predicate_ref.index, token_index));
+            },
+        );
+
+        let mut expected = vec![];
+        for (token_index, token_text) in tokens.iter().enumerate() {
+            for predicate_ref in matcher.matching_predicates(token_text) {
+                expected.push((predicate_ref.index, token_index));
+            }
+        }
+
+        assert_eq!(scanned.len(), 6);
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn matched_tokens_with_unknown_doc_freq_get_the_default_reciprocal() {
+        let mut matcher = test_set_matcher(&[&["foo", "*"]]);
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        // Also for the cached doc freq:
+        for _ in 0..2 {
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.1))
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo0", &test_util::SegmentTermDb),
+                None
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo1", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.5))
+            );
+        }
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("bar?", &test_util::SegmentTermDb),
+            None
+        );
+    }
+
+    #[test]
+    fn terms_missing_from_the_doc_freq_map_get_the_default_reciprocal() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo?"]]);
+        let mut matcher = SetMatcher::new(
+            compile_regex_set(&predicate_set).unwrap(),
+            &predicate_set,
+            &HashMap::new(),
+        );
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+            Some(DocFreqReciprocal(0.1))
+        );
+    }
+
+    #[test]
+    fn invalidated_term_is_looked_up_again() {
+        let mut matcher = test_set_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        for _ in 0..2 {
+            matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+            matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        }
+        assert_eq!(term_db.calls(), 2);
+
+        matcher.invalidate_term("foo1");
+
+        matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn transferred_cache_saves_doc_freq_calls_after_rebuild() {
+        let mut old_matcher = test_set_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        old_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        old_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        let mut new_matcher = test_set_matcher(&[&["foo", "*"], &["*", "bar"]]);
+        new_matcher.transfer_cache(&old_matcher);
+
+        new_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        new_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        new_matcher.lookup_doc_freq_reciprocal("xbar", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn match_mask_agrees_with_lookups() {
+        let mut matcher = test_set_matcher(&[&["foo"], &["ba", "*"], &["*", "qux"], &["[1-9]"]]);
+        let tokens = ["foo", "fo", "bar", "b", "aqux", "quxa", "5", "10", ""];
+
+        let mask = matcher.match_mask(&tokens);
+
+        assert_eq!(mask.len(), tokens.len());
+        for (index, token) in tokens.iter().enumerate() {
+            assert_eq!(
+                mask.contains(index),
+                test_util::matches(&mut matcher, token),
+                "{}",
+                token
+            );
+        }
+        assert_eq!(mask.count_ones(..), 4);
+    }
+
+    #[test]
+    fn matched_literal_spans_leave_out_wildcard_matched_characters() {
+        let mut matcher =
+            test_set_matcher(&[&["foo", "*", "bar"], &["qux"], &["n", "[1-9]", "*", "m"]]);
+
+        assert_eq!(
+            matcher.matched_literal_spans("fooXYZbar"),
+            Some(vec![0..3, 6..9])
+        );
+        assert_eq!(
+            matcher.matched_literal_spans("foobar"),
+            Some(vec![0..3, 3..6])
+        );
+        let whole_term = 0..3;
+        assert_eq!(matcher.matched_literal_spans("qux"), Some(vec![whole_term]));
+        assert_eq!(
+            matcher.matched_literal_spans("n5xxm"),
+            Some(vec![0..1, 4..5])
+        );
+        assert_eq!(matcher.matched_literal_spans("fooXYZ"), None);
+
+        // The groups of a raw expression are not literals:
+        let predicate_set: MatchPredicateSet =
+            std::iter::once(MatchPredicate::Raw("(ab)c".to_string())).collect();
+        let mut matcher = SetMatcher::new(
+            compile_regex_set(&predicate_set).unwrap(),
+            &predicate_set,
+            &HashMap::new(),
+        );
+        assert_eq!(matcher.matched_literal_spans("abc"), Some(vec![]));
+    }
+
+    #[test]
+    fn match_count_counts_every_matching_predicate() {
+        let matcher = test_set_matcher(&[&["foo", "*"], &["*", "bar"], &["foobar"], &["qux"]]);
+
+        assert_eq!(matcher.match_count("nope"), 0);
+        assert_eq!(matcher.match_count("qux"), 1);
+        assert_eq!(matcher.match_count("foobaz"), 1);
+        assert_eq!(matcher.match_count("foobar"), 3);
+    }
+
+    #[test]
+    fn matches_by_specificity_puts_exact_term_first() {
+        let matcher = test_set_matcher(&[
+            &["foo", "*"],
+            &["*", "bar"],
+            &["foobar"],
+            &["*", "o", "*"],
+            &["fo", "*"],
+        ]);
+
+        let specificity_order: Vec<String> = matcher
+            .matches_by_specificity("foobar")
+            .into_iter()
+            .map(|predicate_ref| {
+                glob::to_glob_strings(&vec![predicate_ref.predicate.clone()].into_iter().collect())
+                    .remove(0)
+            })
+            .collect();
+
+        assert_eq!(
+            specificity_order,
+            vec!["foobar", "foo*", "*bar", "fo*", "*o*"]
+        );
+    }
+
+    #[test]
+    fn attribution_is_exact_per_predicate() {
+        use crate::{PatternAST, PatternASTNode};
+
+        let term = MatchPredicate::Term("foo".to_string(), TermSemantics::Exact);
+        let literal_pattern =
+            MatchPredicate::Pattern(PatternAST(vec![PatternASTNode::Literal("foo".to_string())]));
+        let prefix_pattern = MatchPredicate::Pattern(PatternAST(vec![
+            PatternASTNode::Literal("foo".to_string()),
+            PatternASTNode::Wildcard,
+        ]));
+
+        let predicate_set: MatchPredicateSet = vec![
+            term.clone(),
+            literal_pattern.clone(),
+            prefix_pattern.clone(),
+        ]
+        .into_iter()
+        .collect();
+
+        let matcher = SetMatcher::new(
+            compile_regex_set(&predicate_set).unwrap(),
+            &predicate_set,
+            &HashMap::new(),
+        );
+
+        // The combined regex groups the term and the single-literal pattern together,
+        // and its first alternative `^(foo)$` shadows the other two.
+        // Per-predicate compilation keeps all three apart:
+        assert_eq!(
+            matcher.matching_predicates("foo"),
+            vec![
+                PredicateRef {
+                    index: 0,
+                    predicate: &term
+                },
+                PredicateRef {
+                    index: 1,
+                    predicate: &literal_pattern
+                },
+                PredicateRef {
+                    index: 2,
+                    predicate: &prefix_pattern
+                },
+            ]
+        );
+        assert_eq!(
+            matcher.attribute("foobar"),
+            Some(PredicateRef {
+                index: 2,
+                predicate: &prefix_pattern
+            })
+        );
+        assert_eq!(matcher.attribute("bar"), None);
+    }
+
+    #[test]
+    fn lookup_best_score_returns_score_of_rarest_matching_predicate() {
+        struct CommonTermDb;
+
+        impl GetDocFreq for CommonTermDb {
+            fn get_doc_freq(&self, _: crate::TermRef<'_>) -> u64 {
+                99
+            }
+        }
+
+        let predicate_set = test_util::create_predicate_set(&[&["foobar"], &["foo", "*"]]);
+        let common = DocFreqReciprocal::from_doc_freq(99).unwrap();
+        let rare = DocFreqReciprocal::from_doc_freq(1).unwrap();
+
+        // The term is common, but the token matched by the pattern is rare:
+        let mut matcher = SetMatcher::new(
+            compile_regex_set(&predicate_set).unwrap(),
+            &predicate_set,
+            &maplit::hashmap! { "foobar".to_string() => common.clone() },
+        );
+        assert_eq!(
+            matcher.lookup_best_score("foobar", &test_util::AnyTermDb),
+            Some(rare.clone())
+        );
+
+        // The term is rare, but the token matched by the pattern is common:
+        let mut matcher = SetMatcher::new(
+            compile_regex_set(&predicate_set).unwrap(),
+            &predicate_set,
+            &maplit::hashmap! { "foobar".to_string() => rare.clone() },
+        );
+        assert_eq!(
+            matcher.lookup_best_score("foobar", &CommonTermDb),
+            Some(rare)
+        );
+        assert_eq!(
+            matcher.lookup_best_score("foobaz", &CommonTermDb),
+            Some(common)
+        );
+        assert_eq!(matcher.lookup_best_score("bar", &CommonTermDb), None);
+    }
+
+    #[test]
+    fn lookup_matches_whole_tokens_only() {
+        let mut matcher = test_set_matcher(&[&["foo", "*"], &["qux"]]);
+
+        assert!(test_util::matches(&mut matcher, "qux"));
+        assert!(test_util::matches(&mut matcher, "foobar"));
+        assert!(!test_util::matches(&mut matcher, "quxx"));
+        assert!(!test_util::matches(&mut matcher, "barfoo"));
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use regex_automata::dense::DenseDFA;
+use regex_automata::DFA;
+
+use super::regex_util::{
+    predicate_to_regex_expr, required_features, CompileOptions, WILDCARD_EXPR,
+};
+use super::*;
+
+const DEAD_STATE: u32 = 0;
+
+///
+/// A DFA whose accepting states are tagged with the predicate they accept,
+/// so a single pass over a token both matches and attributes it.
+///
+/// It is the product of one anchored DFA per predicate:
+/// each state tracks the state of every predicate's DFA,
+/// and is tagged with the first (in predicate set order) predicate accepting in that state.
+///
+/// CPU and memory usage alert: the product may have far more states than
+/// the combined `Automaton`, especially for many overlapping wildcard patterns.
+/// Use `compile_tagged_automaton_with_options` to limit the wildcards per pattern.
+///
+pub struct TaggedAutomaton {
+    // Row-major transition table, one row of 256 entries per state
+    transitions: Vec<u32>,
+    // The predicate index accepted in each state, if any
+    accept_tags: Vec<Option<usize>>,
+    start_state: u32,
+}
+
+impl TaggedAutomaton {
+    ///
+    /// The index of the first predicate matching the whole token, found in a single pass.
+    ///
+    pub fn find_predicate(&self, bytes: &[u8]) -> Option<usize> {
+        let mut state = self.start_state;
+
+        for byte in bytes {
+            state = self.transitions[state as usize * 256 + *byte as usize];
+            if state == DEAD_STATE {
+                return None;
+            }
+        }
+
+        self.accept_tags[state as usize]
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accept_tags.len()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.transitions.len() * std::mem::size_of::<u32>()
+            + self.accept_tags.len() * std::mem::size_of::<Option<usize>>()
+    }
+}
+
+///
+/// Matcher backed by a `TaggedAutomaton`.
+///
+/// Like the `SetMatcher`, attribution is exact,
+/// but matching and attribution take one DFA pass instead of running a regex set.
+///
+pub struct TaggedMatcher {
+    automaton: Arc<TaggedAutomaton>,
+
+    // The predicates, indexed by the tags of the automaton
+    predicates: Vec<MatchPredicate>,
+
+    term_doc_freq_reciprocals_map: HashMap<String, DocFreqReciprocal>,
+    pattern_doc_freq_cache: HashMap<String, Option<DocFreqReciprocal>>,
+
+    default_reciprocal: Option<DocFreqReciprocal>,
+
+    metrics: Metrics,
+}
+
+impl TaggedMatcher {
+    pub fn new(
+        automaton: Arc<TaggedAutomaton>,
+        predicate_set: &MatchPredicateSet,
+        term_doc_freq_reciprocals_map: &HashMap<String, DocFreqReciprocal>,
+    ) -> Self {
+        Self {
+            automaton,
+            predicates: predicate_set.iter().cloned().collect(),
+            term_doc_freq_reciprocals_map: term_doc_freq_reciprocals_map.clone(),
+            pattern_doc_freq_cache: HashMap::new(),
+            default_reciprocal: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    ///
+    /// Set the reciprocal of matched tokens with an unknown doc freq,
+    /// including term predicates missing from the doc freq map,
+    /// see `RegexMatcher::set_default_reciprocal`.
+    ///
+    pub fn set_default_reciprocal(&mut self, default_reciprocal: Option<DocFreqReciprocal>) {
+        self.default_reciprocal = default_reciprocal;
+    }
+
+    ///
+    /// Forget the cached doc freq of a token, see `RegexMatcher::invalidate_term`.
+    /// Term predicates are not affected.
+    ///
+    pub fn invalidate_term(&mut self, term_text: &str) {
+        self.pattern_doc_freq_cache.remove(term_text);
+    }
+
+    ///
+    /// Inherit the doc freqs cached by another tagged matcher, see `RegexMatcher::transfer_cache`.
+    ///
+    pub fn transfer_cache(&mut self, from: &Self) {
+        for (token_text, doc_freq_reciprocal) in &from.pattern_doc_freq_cache {
+            self.pattern_doc_freq_cache
+                .entry(token_text.clone())
+                .or_insert_with(|| doc_freq_reciprocal.clone());
+        }
+    }
+
+    ///
+    /// The first predicate (in predicate set order) matching the token.
+    ///
+    pub fn attribute(&self, token_text: &str) -> Option<PredicateRef<'_>> {
+        let index = self.automaton.find_predicate(token_text.as_bytes())?;
+
+        Some(PredicateRef {
+            index,
+            predicate: &self.predicates[index],
+        })
+    }
+}
+
+impl LookupDocFreqReciprocal for TaggedMatcher {
+    fn lookup_doc_freq_reciprocal(
+        &mut self,
+        token_text: &str,
+        get_doc_freq: &impl GetDocFreq,
+    ) -> Option<DocFreqReciprocal> {
+        self.metrics.lookups += 1;
+
+        let predicate_index = self.automaton.find_predicate(token_text.as_bytes())?;
+
+        self.metrics.matches += 1;
+
+        if let MatchPredicate::Term(term_text, TermSemantics::Exact) =
+            &self.predicates[predicate_index]
+        {
+            self.metrics.cache_hits += 1;
+            return self
+                .term_doc_freq_reciprocals_map
+                .get(term_text)
+                .cloned()
+                .or_else(|| self.default_reciprocal.clone());
+        }
+
+        if let Some(pattern_doc_freq) = self.pattern_doc_freq_cache.get(token_text) {
+            self.metrics.cache_hits += 1;
+            return pattern_doc_freq.clone();
+        }
+
+        self.metrics.cache_misses += 1;
+        self.metrics.get_doc_freq_calls += 1;
+
+        let doc_freq_reciprocal = DocFreqReciprocal::from_try_doc_freq(
+            get_doc_freq.try_get_doc_freq(crate::TermRef::from(token_text)),
+            &self.default_reciprocal,
+        );
+
+        self.pattern_doc_freq_cache
+            .insert(token_text.to_string(), doc_freq_reciprocal.clone());
+
+        doc_freq_reciprocal
+    }
+}
+
+///
+/// Compile a tagged automaton, with tags being indexes into the predicate set (in set order).
+///
+pub fn compile_tagged_automaton(
+    predicate_set: &MatchPredicateSet,
+) -> Result<Arc<TaggedAutomaton>, String> {
+    compile_tagged_automaton_with_options(predicate_set, &CompileOptions::default())
+}
+
+///
+/// Compile a tagged automaton, first checking the predicate set against the limits of the options.
+///
+pub fn compile_tagged_automaton_with_options(
+    predicate_set: &MatchPredicateSet,
+    options: &CompileOptions,
+) -> Result<Arc<TaggedAutomaton>, String> {
+    options
+        .check(predicate_set)
+        .map_err(|error| format!("compile_tagged_automaton: {}", error))?;
+
+    required_features(predicate_set).check_dfa_support("compile_tagged_automaton")?;
+
+    let dfas = predicate_set
+        .iter()
+        .map(|match_predicate| {
+            regex_automata::dense::Builder::new()
+                .anchored(true)
+                .longest_match(true)
+                .build(&predicate_to_regex_expr(match_predicate, WILDCARD_EXPR))
+                .map_err(|error| format!("compile_tagged_automaton failed. {:?}", error))
+        })
+        .collect::<Result<Vec<DenseDFA<Vec<usize>, usize>>, String>>()?;
+
+    let mut builder = ProductBuilder {
+        dfas: &dfas,
+        state_ids: HashMap::new(),
+        // The dead state loops to itself:
+        transitions: vec![DEAD_STATE; 256],
+        accept_tags: vec![None],
+        queue: VecDeque::new(),
+    };
+
+    let start_state = builder.add_state(dfas.iter().map(|dfa| dfa.start_state()).collect());
+
+    while let Some((state_id, dfa_states)) = builder.queue.pop_front() {
+        for byte in 0..=255u8 {
+            let next_dfa_states = dfa_states
+                .iter()
+                .zip(builder.dfas)
+                .map(|(dfa_state, dfa)| dfa.next_state(*dfa_state, byte))
+                .collect();
+
+            let next_state_id = builder.add_state(next_dfa_states);
+            builder.transitions[state_id as usize * 256 + byte as usize] = next_state_id;
+        }
+    }
+
+    Ok(Arc::new(TaggedAutomaton {
+        transitions: builder.transitions,
+        accept_tags: builder.accept_tags,
+        start_state,
+    }))
+}
+
+struct ProductBuilder<'a> {
+    dfas: &'a [DenseDFA<Vec<usize>, usize>],
+    state_ids: HashMap<Vec<usize>, u32>,
+    transitions: Vec<u32>,
+    accept_tags: Vec<Option<usize>>,
+    // States whose transitions are not yet computed
+    queue: VecDeque<(u32, Vec<usize>)>,
+}
+
+impl<'a> ProductBuilder<'a> {
+    fn add_state(&mut self, dfa_states: Vec<usize>) -> u32 {
+        let dfas = self.dfas;
+
+        if dfa_states
+            .iter()
+            .zip(dfas)
+            .all(|(dfa_state, dfa)| dfa.is_dead_state(*dfa_state))
+        {
+            return DEAD_STATE;
+        }
+
+        if let Some(state_id) = self.state_ids.get(&dfa_states) {
+            return *state_id;
+        }
+
+        let state_id = self.accept_tags.len() as u32;
+
+        self.accept_tags.push(
+            dfa_states
+                .iter()
+                .zip(dfas)
+                .position(|(dfa_state, dfa)| dfa.is_match_state(*dfa_state)),
+        );
+        self.transitions
+            .resize(self.transitions.len() + 256, DEAD_STATE);
+        self.state_ids.insert(dfa_states.clone(), state_id);
+        self.queue.push_back((state_id, dfa_states));
+
+        state_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_tagged_matcher(patterns: &[&[&str]]) -> TaggedMatcher {
+        let predicate_set = test_util::create_predicate_set(patterns);
+        let term_doc_freq_reciprocals =
+            test_util::term_doc_freq_reciprocals_from_predicate_set(&predicate_set);
+
+        TaggedMatcher::new(
+            compile_tagged_automaton(&predicate_set).unwrap(),
+            &predicate_set,
+            &term_doc_freq_reciprocals,
+        )
+    }
+
+    #[test]
+    fn matched_tokens_with_unknown_doc_freq_get_the_default_reciprocal() {
+        let mut matcher = test_tagged_matcher(&[&["foo", "*"]]);
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        // Also for the cached doc freq:
+        for _ in 0..2 {
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.1))
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo0", &test_util::SegmentTermDb),
+                None
+            );
+            assert_eq!(
+                matcher.lookup_doc_freq_reciprocal("foo1", &test_util::SegmentTermDb),
+                Some(DocFreqReciprocal(0.5))
+            );
+        }
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("bar?", &test_util::SegmentTermDb),
+            None
+        );
+    }
+
+    #[test]
+    fn terms_missing_from_the_doc_freq_map_get_the_default_reciprocal() {
+        let predicate_set = test_util::create_predicate_set(&[&["foo?"]]);
+        let mut matcher = TaggedMatcher::new(
+            compile_tagged_automaton(&predicate_set).unwrap(),
+            &predicate_set,
+            &HashMap::new(),
+        );
+        matcher.set_default_reciprocal(Some(DocFreqReciprocal(0.1)));
+
+        assert_eq!(
+            matcher.lookup_doc_freq_reciprocal("foo?", &test_util::SegmentTermDb),
+            Some(DocFreqReciprocal(0.1))
+        );
+    }
+
+    #[test]
+    fn invalidated_term_is_looked_up_again() {
+        let mut matcher = test_tagged_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        for _ in 0..2 {
+            matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+            matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        }
+        assert_eq!(term_db.calls(), 2);
+
+        matcher.invalidate_term("foo1");
+
+        matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn transferred_cache_saves_doc_freq_calls_after_rebuild() {
+        let mut old_matcher = test_tagged_matcher(&[&["foo", "*"]]);
+        let term_db = test_util::CountingTermDb::default();
+
+        old_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        old_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        let mut new_matcher = test_tagged_matcher(&[&["foo", "*"], &["*", "bar"]]);
+        new_matcher.transfer_cache(&old_matcher);
+
+        new_matcher.lookup_doc_freq_reciprocal("foo1", &term_db);
+        new_matcher.lookup_doc_freq_reciprocal("foo2", &term_db);
+        assert_eq!(term_db.calls(), 2);
+
+        new_matcher.lookup_doc_freq_reciprocal("xbar", &term_db);
+        assert_eq!(term_db.calls(), 3);
+    }
+
+    #[test]
+    fn single_pass_attributes_exactly() {
+        let patterns: &[&[&str]] = &[&["foo"], &["fo", "*"], &["*", "oo"], &["[1-100]"]];
+        let tagged_matcher = test_tagged_matcher(patterns);
+        let set_matcher = set_matcher::test::test_set_matcher(patterns);
+
+        let attributed_predicate = |token_text| {
+            tagged_matcher
+                .attribute(token_text)
+                .map(|predicate_ref| predicate_ref.predicate.clone())
+        };
+
+        assert_eq!(
+            attributed_predicate("foo"),
+            Some(MatchPredicate::Term(
+                "foo".to_string(),
+                TermSemantics::Exact
+            ))
+        );
+        assert_eq!(
+            attributed_predicate("fox"),
+            test_util::create_predicate_set(&[&["fo", "*"]])
+                .into_iter()
+                .next()
+        );
+        assert_eq!(
+            attributed_predicate("boo"),
+            test_util::create_predicate_set(&[&["*", "oo"]])
+                .into_iter()
+                .next()
+        );
+        assert_eq!(
+            attributed_predicate("100"),
+            test_util::create_predicate_set(&[&["[1-100]"]])
+                .into_iter()
+                .next()
+        );
+        assert_eq!(attributed_predicate("bar"), None);
+
+        for token_text in &[
+            "foo", "fox", "foooo", "boo", "oo", "7", "10", "100", "101", "",
+        ] {
+            assert_eq!(
+                tagged_matcher.attribute(token_text),
+                set_matcher.attribute(token_text),
+                "{}",
+                token_text
+            );
+        }
+    }
+
+    #[test]
+    fn empty_predicate_set_matches_nothing() {
+        let mut matcher = test_tagged_matcher(&[]);
+
+        assert!(!test_util::matches(&mut matcher, ""));
+        assert!(!test_util::matches(&mut matcher, "foo"));
+    }
+
+    #[test]
+    fn too_many_wildcards_per_pattern_is_an_error() {
+        let predicate_set =
+            test_util::create_predicate_set(&[&["foo"], &["a", "*", "b", "*", "c", "*", "d"]]);
+        let options = CompileOptions {
+            max_wildcards_per_pattern: Some(2),
+            ..CompileOptions::default()
+        };
+
+        assert_eq!(
+            compile_tagged_automaton_with_options(&predicate_set, &options).err(),
+            Some(
+                "compile_tagged_automaton: pattern a*b*c*d has 3 wildcards, the maximum is 2"
+                    .to_string()
+            )
+        );
+        assert!(compile_tagged_automaton(&predicate_set).is_ok());
+    }
+}
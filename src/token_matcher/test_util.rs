@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 
 use crate::*;
@@ -5,37 +6,52 @@ use super::*;
 
 ///
 /// Generate a match predicate set from test patterns, e.g.:
-/// &[&["bar"], &["*", "foo"]]
-/// a pattern of length 1 becomes a term predicate, length > 1 becomes a pattern predicate.
+/// &[&["bar"], &["*", "foo"], &["[10-20]"]]
+/// a literal pattern of length 1 becomes a term predicate, anything else becomes a pattern predicate.
 ///
 pub fn create_predicate_set(patterns: &[&[&str]]) -> MatchPredicateSet {
     patterns
         .iter()
         .map(|pattern| {
-            if pattern.len() == 1 {
-                MatchPredicate::Term(pattern[0].to_string())
-            } else {
-                MatchPredicate::Pattern(PatternAST(
-                    pattern
-                        .iter()
-                        .map(|str| match *str {
-                            "*" => PatternASTNode::Wildcard,
-                            _ => PatternASTNode::Literal(str.to_string()),
-                        })
-                        .collect(),
-                ))
+            let nodes: Vec<PatternASTNode> = pattern.iter().map(|str| create_node(str)).collect();
+            match nodes.as_slice() {
+                [PatternASTNode::Literal(text)] => {
+                    MatchPredicate::Term(text.clone(), TermSemantics::Exact)
+                }
+                _ => MatchPredicate::Pattern(PatternAST(nodes)),
             }
         })
         .collect()
 }
 
+///
+/// "*" is a wildcard, "[min-max]" is a numeric range, anything else is a literal.
+///
+fn create_node(str: &str) -> PatternASTNode {
+    if str == "*" {
+        return PatternASTNode::Wildcard;
+    }
+
+    if str.len() > 2 && str.starts_with('[') && str.ends_with(']') {
+        let range = &str[1..str.len() - 1];
+        if let Some(separator) = range[1..].find('-').map(|index| index + 1) {
+            if let (Ok(min), Ok(max)) = (range[..separator].parse(), range[separator + 1..].parse())
+            {
+                return PatternASTNode::NumRange { min, max };
+            }
+        }
+    }
+
+    PatternASTNode::Literal(str.to_string())
+}
+
 pub fn term_doc_freq_reciprocals_from_predicate_set(
     predicate_set: &MatchPredicateSet,
 ) -> HashMap<String, DocFreqReciprocal> {
     let mut term_doc_freq_reciprocals: HashMap<String, DocFreqReciprocal> = HashMap::new();
 
     for match_predicate in predicate_set.iter() {
-        if let MatchPredicate::Term(term_text) = match_predicate {
+        if let MatchPredicate::Term(term_text, TermSemantics::Exact) = match_predicate {
             term_doc_freq_reciprocals.insert(
                 term_text.to_owned(),
                 DocFreqReciprocal::from_doc_freq(1).unwrap(),
@@ -45,3 +61,80 @@ pub fn term_doc_freq_reciprocals_from_predicate_set(
 
     term_doc_freq_reciprocals
 }
+
+///
+/// Term database where every term exists in exactly one document.
+///
+pub struct AnyTermDb;
+
+impl GetDocFreq for AnyTermDb {
+    fn get_doc_freq(&self, _: crate::TermRef<'_>) -> u64 {
+        1
+    }
+}
+
+///
+/// Whether the matcher produces a doc freq reciprocal for the token.
+///
+pub fn matches(matcher: &mut impl LookupDocFreqReciprocal, token_text: &str) -> bool {
+    matcher
+        .lookup_doc_freq_reciprocal(token_text, &AnyTermDb)
+        .is_some()
+}
+
+///
+/// Term database where every term exists in no document.
+///
+pub struct ZeroTermDb;
+
+impl GetDocFreq for ZeroTermDb {
+    fn get_doc_freq(&self, _: crate::TermRef<'_>) -> u64 {
+        0
+    }
+}
+
+///
+/// Term database not knowing the doc freq of terms ending with `?`,
+/// where terms ending with `0` exist in no document, and other terms in exactly one document.
+///
+pub struct SegmentTermDb;
+
+impl GetDocFreq for SegmentTermDb {
+    fn get_doc_freq(&self, term: crate::TermRef<'_>) -> u64 {
+        self.try_get_doc_freq(term).unwrap_or(0)
+    }
+
+    fn try_get_doc_freq(&self, term: crate::TermRef<'_>) -> Option<u64> {
+        let term_text = term.text();
+
+        if term_text.ends_with('?') {
+            None
+        } else if term_text.ends_with('0') {
+            Some(0)
+        } else {
+            Some(1)
+        }
+    }
+}
+
+///
+/// Term database where every term exists in exactly one document,
+/// counting how many times it was asked.
+///
+#[derive(Default)]
+pub struct CountingTermDb {
+    calls: Cell<u64>,
+}
+
+impl CountingTermDb {
+    pub fn calls(&self) -> u64 {
+        self.calls.get()
+    }
+}
+
+impl GetDocFreq for CountingTermDb {
+    fn get_doc_freq(&self, _: crate::TermRef<'_>) -> u64 {
+        self.calls.set(self.calls.get() + 1);
+        1
+    }
+}